@@ -0,0 +1,30 @@
+#![no_main]
+
+extern crate alloc;
+
+use alloc_sys::map::MemoryMap;
+use libfuzzer_sys::fuzz_target;
+use std::alloc::{alloc, dealloc, Layout};
+use std::ptr::NonNull;
+
+/// The allocator modules, built for the host without the rest of the kernel.
+#[allow(dead_code)]
+#[path = "../../src/alloc_sys"]
+mod alloc_sys {
+    pub mod block;
+    pub mod fuzz;
+    pub mod map;
+    pub mod stats;
+}
+
+const MAP_SIZE: usize = 1024 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    let layout = Layout::from_size_align(MAP_SIZE, 4096).unwrap();
+    unsafe {
+        let buffer = alloc(layout);
+        let mut map = MemoryMap::new(NonNull::new(buffer).unwrap(), MAP_SIZE);
+        alloc_sys::fuzz::run_sequence(&mut map, data);
+        dealloc(buffer, layout);
+    }
+});
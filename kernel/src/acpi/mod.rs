@@ -0,0 +1,197 @@
+use core::mem::size_of;
+use core::ptr;
+
+pub const MAX_IO_APICS: usize = 4;
+pub const MAX_INTERRUPT_OVERRIDES: usize = 16;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+const MADT_ENTRY_IO_APIC: u8 = 1;
+const MADT_ENTRY_INTERRUPT_OVERRIDE: u8 = 2;
+const MADT_ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcpiError {
+    InvalidRsdp,
+    InvalidChecksum(&'static str),
+    MadtNotFound,
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: u64,
+    pub gsi_base: u32,
+}
+
+/// Remaps a legacy ISA IRQ to a different global system interrupt, with its own polarity and
+/// trigger mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub source: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+impl InterruptOverride {
+    pub fn is_active_low(&self) -> bool {
+        self.flags & 0b11 == 0b11
+    }
+
+    pub fn is_level_triggered(&self) -> bool {
+        (self.flags >> 2) & 0b11 == 0b11
+    }
+}
+
+/// The interrupt controller layout described by the MADT.
+#[derive(Debug, Clone)]
+pub struct MadtInfo {
+    pub local_apic_address: u64,
+    pub has_legacy_pics: bool,
+    pub io_apics: [Option<IoApicInfo>; MAX_IO_APICS],
+    pub overrides: [Option<InterruptOverride>; MAX_INTERRUPT_OVERRIDES],
+}
+
+impl MadtInfo {
+    /// Returns the global system interrupt an ISA IRQ is wired to, together with its override.
+    pub fn isa_irq_to_gsi(&self, irq: u8) -> (u32, Option<InterruptOverride>) {
+        match self.overrides.iter().flatten().find(|o| o.source == irq) {
+            Some(o) => (o.gsi, Some(*o)),
+            None => (irq as u32, None),
+        }
+    }
+}
+
+/// Finds and parses the MADT, reading the ACPI tables through the physical memory mapping.
+pub fn find_madt(rsdp_addr: u64, physical_memory_offset: u64) -> Result<MadtInfo, AcpiError> {
+    let phys = |addr: u64| (addr + physical_memory_offset) as *const u8;
+
+    let rsdp = unsafe { ptr::read_unaligned(phys(rsdp_addr) as *const Rsdp) };
+    if &rsdp.signature != RSDP_SIGNATURE {
+        return Err(AcpiError::InvalidRsdp);
+    }
+    if !unsafe { is_checksum_valid(phys(rsdp_addr), 20) } {
+        return Err(AcpiError::InvalidChecksum("RSDP"));
+    }
+
+    // ACPI 2.0+ provides the XSDT, which uses 64-bit pointers.
+    let (sdt_addr, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, size_of::<u64>())
+    } else {
+        (rsdp.rsdt_address as u64, size_of::<u32>())
+    };
+    let sdt = unsafe { read_sdt_header(phys(sdt_addr)) };
+    if !unsafe { is_checksum_valid(phys(sdt_addr), sdt.length as usize) } {
+        return Err(AcpiError::InvalidChecksum("RSDT"));
+    }
+
+    let entries = (sdt.length as usize - size_of::<SdtHeader>()) / entry_size;
+    for i in 0..entries {
+        let entry_ptr = unsafe { phys(sdt_addr).add(size_of::<SdtHeader>() + i * entry_size) };
+        let table_addr = unsafe {
+            match entry_size {
+                8 => ptr::read_unaligned(entry_ptr as *const u64),
+                _ => ptr::read_unaligned(entry_ptr as *const u32) as u64,
+            }
+        };
+        let header = unsafe { read_sdt_header(phys(table_addr)) };
+        if &header.signature == MADT_SIGNATURE {
+            if !unsafe { is_checksum_valid(phys(table_addr), header.length as usize) } {
+                return Err(AcpiError::InvalidChecksum("MADT"));
+            }
+            return Ok(unsafe { parse_madt(phys(table_addr), header.length as usize) });
+        }
+    }
+
+    Err(AcpiError::MadtNotFound)
+}
+
+unsafe fn parse_madt(madt: *const u8, length: usize) -> MadtInfo {
+    let body = madt.add(size_of::<SdtHeader>());
+    let local_apic_address = ptr::read_unaligned(body as *const u32) as u64;
+    let flags = ptr::read_unaligned(body.add(4) as *const u32);
+    let mut info = MadtInfo {
+        local_apic_address,
+        has_legacy_pics: flags & 1 == 1,
+        io_apics: [None; MAX_IO_APICS],
+        overrides: [None; MAX_INTERRUPT_OVERRIDES],
+    };
+
+    let mut offset = size_of::<SdtHeader>() + 8;
+    while offset + 2 <= length {
+        let entry = madt.add(offset);
+        let entry_type = *entry;
+        let entry_length = *entry.add(1) as usize;
+        if entry_length < 2 {
+            break;
+        }
+
+        match entry_type {
+            MADT_ENTRY_IO_APIC => {
+                let io_apic = IoApicInfo {
+                    id: *entry.add(2),
+                    address: ptr::read_unaligned(entry.add(4) as *const u32) as u64,
+                    gsi_base: ptr::read_unaligned(entry.add(8) as *const u32),
+                };
+                if let Some(slot) = info.io_apics.iter_mut().find(|s| s.is_none()) {
+                    *slot = Some(io_apic);
+                }
+            }
+            MADT_ENTRY_INTERRUPT_OVERRIDE => {
+                let interrupt_override = InterruptOverride {
+                    source: *entry.add(3),
+                    gsi: ptr::read_unaligned(entry.add(4) as *const u32),
+                    flags: ptr::read_unaligned(entry.add(8) as *const u16),
+                };
+                if let Some(slot) = info.overrides.iter_mut().find(|s| s.is_none()) {
+                    *slot = Some(interrupt_override);
+                }
+            }
+            MADT_ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                info.local_apic_address = ptr::read_unaligned(entry.add(4) as *const u64);
+            }
+            _ => {}
+        }
+
+        offset += entry_length;
+    }
+
+    info
+}
+
+unsafe fn read_sdt_header(ptr: *const u8) -> SdtHeader {
+    ptr::read_unaligned(ptr as *const SdtHeader)
+}
+
+unsafe fn is_checksum_valid(ptr: *const u8, len: usize) -> bool {
+    (0..len).fold(0u8, |sum, i| sum.wrapping_add(*ptr.add(i))) == 0
+}
@@ -0,0 +1,151 @@
+use crate::alloc_sys::map::MemoryMap;
+use crate::alloc_sys::{
+    allocate_with, deallocate_with, resize_with, AllocatorError, MIN_ARENA_SIZE,
+};
+use crate::sync::SpinLock;
+use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+
+/// Hands out memory from a single region of memory, such as a DMA-safe region or the heap of a
+/// process, independently of the system allocator.
+///
+/// The region is not owned by the arena, so dropping it does not free anything.
+pub struct ArenaAllocator {
+    map: SpinLock<MemoryMap>,
+}
+
+impl ArenaAllocator {
+    /// # Safety
+    /// The `len` bytes at `ptr` must be valid, and used by nothing else while the arena lives.
+    pub unsafe fn new(ptr: NonNull<u8>, len: usize) -> Result<Self, AllocatorError> {
+        if len < MIN_ARENA_SIZE {
+            return Err(AllocatorError::ArenaTooSmall(len));
+        }
+        Ok(Self {
+            map: SpinLock::new(MemoryMap::new(ptr, len)),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.map.lock_irqsave().capacity()
+    }
+
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.map.lock_irqsave().contains(ptr)
+    }
+}
+
+unsafe impl GlobalAlloc for ArenaAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.map
+            .lock_irqsave()
+            .alloc(layout.size(), layout.align(), false)
+            .unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        let mut map = self.map.lock_irqsave();
+        if map.contains(ptr) {
+            map.dealloc(ptr);
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.map
+            .lock_irqsave()
+            .alloc(layout.size(), layout.align(), true)
+            .unwrap_or(null_mut())
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let mut map = self.map.lock_irqsave();
+        if !map.contains(ptr) {
+            return null_mut();
+        }
+        map.realloc(ptr, new_size, layout.align())
+            .unwrap_or(null_mut())
+    }
+}
+
+unsafe impl Allocator for ArenaAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { allocate_with(self, layout, false) }
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { allocate_with(self, layout, true) }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        deallocate_with(self, ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        resize_with(self, ptr, old_layout, new_layout, false)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        resize_with(self, ptr, old_layout, new_layout, true)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        resize_with(self, ptr, old_layout, new_layout, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alloc_sys::arena::ArenaAllocator;
+    use crate::alloc_sys::MIN_ARENA_SIZE;
+    use crate::utils::heap_vec::HeapVec;
+    use alloc::alloc::alloc;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+
+    #[test]
+    fn arena_allocator_test() {
+        unsafe {
+            let layout = Layout::from_size_align(MIN_ARENA_SIZE, 4096).unwrap();
+            let arena =
+                ArenaAllocator::new(NonNull::new(alloc(layout)).unwrap(), MIN_ARENA_SIZE).unwrap();
+
+            let mut vec = Vec::new_in(&arena);
+            for i in 0..1000u32 {
+                vec.push(i);
+            }
+            assert!(arena.contains(vec.as_ptr().cast()));
+            assert!(vec.iter().copied().eq(0..1000));
+
+            let boxed = Box::new_in([7u8; 100], &arena);
+            assert!(arena.contains(boxed.as_ptr()));
+
+            let mut heap_vec = HeapVec::new(16, &arena).unwrap();
+            heap_vec.push(1u64);
+            assert!(arena.contains(heap_vec.as_ptr().cast()));
+
+            // Zero sized allocations never touch the arena
+            let empty = Box::new_in((), &arena);
+            assert!(!arena.contains((&*empty as *const ()).cast()));
+
+            // Too big for the arena
+            assert!(Vec::<u8, _>::try_with_capacity_in(MIN_ARENA_SIZE, &arena).is_err());
+        }
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "alloc-tracking")]
+use crate::alloc_sys::tracking::AllocSite;
 use core::{cmp::Ordering, mem::align_of, num::NonZero};
 
 pub const MEMORY_BLOCK_ALIGN: NonZero<usize> =
@@ -8,6 +10,8 @@ pub struct MemoryBlock {
     pub is_active: bool,
     pub start: usize,
     pub size: usize,
+    #[cfg(feature = "alloc-tracking")]
+    pub site: AllocSite,
 }
 
 impl MemoryBlock {
@@ -16,6 +20,8 @@ impl MemoryBlock {
             is_active: true,
             start,
             size,
+            #[cfg(feature = "alloc-tracking")]
+            site: AllocSite::EMPTY,
         }
     }
 
@@ -24,6 +30,8 @@ impl MemoryBlock {
             is_active: false,
             start: 0,
             size: 0,
+            #[cfg(feature = "alloc-tracking")]
+            site: AllocSite::EMPTY,
         }
     }
 
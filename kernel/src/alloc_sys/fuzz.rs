@@ -0,0 +1,110 @@
+use crate::alloc_sys::map::{MemoryMap, MAX_ALIGN};
+use alloc::vec::Vec;
+
+/// Bytes of input consumed by each step of [`run_sequence`].
+pub const STEP_SIZE: usize = 4;
+
+/// A block handed out by the map, filled with `tag`.
+struct LiveBlock {
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+    tag: u8,
+}
+
+impl LiveBlock {
+    unsafe fn check_tag(&self, len: usize) {
+        let bytes = core::slice::from_raw_parts(self.ptr, len.min(self.size));
+        assert!(
+            bytes.iter().all(|&byte| byte == self.tag),
+            "The block at {:p} ({} bytes) was overwritten",
+            self.ptr,
+            self.size
+        );
+    }
+}
+
+/// Decodes allocations, reallocations and frees from `data`, runs them on `map`, and checks after
+/// every step that the blocks stay aligned, in bounds and apart from each other, and that the map
+/// is consistent. Everything still allocated is freed at the end.
+///
+/// Each step reads [`STEP_SIZE`] bytes: the operation, a size in the next two, and an alignment
+/// or the index of the block to work on in the last one.
+///
+/// # Safety
+/// `map` must own its buffer.
+pub unsafe fn run_sequence(map: &mut MemoryMap, data: &[u8]) {
+    let mut live: Vec<LiveBlock> = Vec::new();
+    for (step, op) in data.chunks_exact(STEP_SIZE).enumerate() {
+        let size = u16::from_le_bytes([op[1], op[2]]) as usize;
+        match op[0] % 4 {
+            2 if !live.is_empty() => {
+                let block = live.swap_remove(op[3] as usize % live.len());
+                block.check_tag(block.size);
+                map.dealloc(block.ptr);
+            }
+            3 if !live.is_empty() => {
+                let index = op[3] as usize % live.len();
+                let block = &mut live[index];
+                block.check_tag(block.size);
+                if let Some(ptr) = map.realloc(block.ptr, size, block.align) {
+                    block.ptr = ptr;
+                    block.check_tag(size);
+                    block.size = size;
+                    ptr.write_bytes(block.tag, size);
+                }
+            }
+            _ => {
+                let align = 1 << (op[3] as u32 % (MAX_ALIGN.trailing_zeros() + 1));
+                if let Some(ptr) = map.alloc(size, align, op[0] & 0x80 != 0) {
+                    if op[0] & 0x80 != 0 {
+                        assert!(core::slice::from_raw_parts(ptr, size)
+                            .iter()
+                            .all(|&byte| byte == 0));
+                    }
+                    let tag = step as u8;
+                    ptr.write_bytes(tag, size);
+                    live.push(LiveBlock {
+                        ptr,
+                        size,
+                        align,
+                        tag,
+                    });
+                }
+            }
+        }
+        check_blocks(map, &mut live);
+    }
+
+    for block in live.drain(..) {
+        block.check_tag(block.size);
+        map.dealloc(block.ptr);
+    }
+    map.check_consistency();
+    assert_eq!(map.stats().live_blocks, 0);
+    assert!(map.blocks().all(|block| !block.is_active));
+}
+
+fn check_blocks(map: &MemoryMap, live: &mut [LiveBlock]) {
+    map.check_consistency();
+    let blocks = map.blocks().collect::<Vec<_>>();
+    assert!(blocks.is_sorted_by_key(|block| block.start));
+    assert_eq!(
+        blocks.iter().filter(|block| block.is_active).count(),
+        live.len()
+    );
+
+    live.sort_by_key(|block| block.ptr);
+    for block in live.iter() {
+        assert!(block.ptr.addr().is_multiple_of(block.align));
+        assert!(map.contains(block.ptr) && map.contains(block.ptr.wrapping_add(block.size)));
+    }
+    for pair in live.windows(2) {
+        assert!(
+            pair[0].ptr.wrapping_add(pair[0].size) <= pair[1].ptr,
+            "The blocks at {:p} and {:p} overlap",
+            pair[0].ptr,
+            pair[1].ptr
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use crate::alloc_sys::block::MemoryBlock;
+use crate::alloc_sys::map::MemoryMap;
+use crate::alloc_sys::stats::AllocatorStats;
+#[cfg(feature = "alloc-tracking")]
+use crate::alloc_sys::tracking::AllocSite;
+use crate::alloc_sys::AllocatorError;
+use core::ptr::NonNull;
+
+pub const HEAP_PAGE_SIZE: usize = 4096;
+/// The heap grows by at least this much at a time, and keeps this much unused space when it
+/// shrinks, so that alternating allocations do not map and unmap the same pages over and over.
+pub const HEAP_GROW_STEP: usize = 64 * 1024;
+
+/// Backs the pages of a [`GrowableHeap`] with physical memory.
+pub trait PageProvider: Sync {
+    /// Makes the `len` bytes at `ptr` usable. Returns `false` if there is no memory left.
+    ///
+    /// # Safety
+    /// `ptr` and `len` must be page aligned and must lie within the reserved range of the heap.
+    unsafe fn map(&self, ptr: NonNull<u8>, len: usize) -> bool;
+
+    /// Gives back the memory behind the `len` bytes at `ptr`.
+    ///
+    /// # Safety
+    /// The pages must have been mapped with [`PageProvider::map`], and must not be in use.
+    unsafe fn unmap(&self, ptr: NonNull<u8>, len: usize);
+}
+
+/// A heap living in a reserved range of virtual memory, which maps pages on demand as it fills
+/// up and unmaps them again once its tail is unused.
+pub struct GrowableHeap {
+    map: MemoryMap,
+    buffer: NonNull<u8>,
+    mapped: usize,
+    reserved: usize,
+    pages: &'static dyn PageProvider,
+}
+
+unsafe impl Send for GrowableHeap {}
+
+impl GrowableHeap {
+    /// # Safety
+    /// The `len` bytes at `ptr` must be reserved for this heap, and must not be mapped yet.
+    pub unsafe fn new(
+        ptr: NonNull<u8>,
+        len: usize,
+        pages: &'static dyn PageProvider,
+    ) -> Result<Self, AllocatorError> {
+        if !ptr.addr().get().is_multiple_of(HEAP_PAGE_SIZE) || len < HEAP_GROW_STEP {
+            return Err(AllocatorError::ArenaTooSmall(len));
+        }
+        if !pages.map(ptr, HEAP_GROW_STEP) {
+            return Err(AllocatorError::OutOfMemory);
+        }
+        Ok(Self {
+            map: MemoryMap::new(ptr, HEAP_GROW_STEP),
+            buffer: ptr,
+            mapped: HEAP_GROW_STEP,
+            reserved: len,
+            pages,
+        })
+    }
+
+    /// Number of bytes currently mapped for allocations.
+    pub fn capacity(&self) -> usize {
+        self.mapped
+    }
+
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let start = self.buffer.as_ptr() as *const u8;
+        ptr >= start && ptr < start.wrapping_add(self.reserved)
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        self.map.stats()
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = MemoryBlock> + '_ {
+        self.map.blocks()
+    }
+
+    #[cfg(feature = "alloc-tracking")]
+    pub unsafe fn set_site(&mut self, ptr: *mut u8, site: AllocSite) {
+        self.map.set_site(ptr, site);
+    }
+
+    /// Allocates from the mapped part of the heap, mapping more pages when it is full.
+    pub unsafe fn alloc(&mut self, size: usize, align: usize, zeroed: bool) -> Option<*mut u8> {
+        if let Some(ptr) = self.map.alloc(size, align, zeroed) {
+            return Some(ptr);
+        }
+        let needed = MemoryMap::required_chunk_size(size, align)?;
+        if !self.grow(needed.max(HEAP_GROW_STEP)) {
+            return None;
+        }
+        self.map.alloc(size, align, zeroed)
+    }
+
+    /// Resizes the block at `ptr`, mapping more pages if it does not fit anywhere else.
+    pub unsafe fn realloc(&mut self, ptr: *mut u8, size: usize, align: usize) -> Option<*mut u8> {
+        if let Some(new_ptr) = self.map.realloc(ptr, size, align) {
+            return Some(new_ptr);
+        }
+        let needed = MemoryMap::required_chunk_size(size, align)?;
+        if !self.grow(needed.max(HEAP_GROW_STEP)) {
+            return None;
+        }
+        self.map.realloc(ptr, size, align)
+    }
+
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        self.map.dealloc(ptr);
+        self.release_unused();
+    }
+
+    /// Unmaps the pages past the last block, keeping [`HEAP_GROW_STEP`] bytes of them around.
+    pub fn release_unused(&mut self) {
+        let keep = page_align_up(self.map.used_len() + HEAP_GROW_STEP);
+        if keep + HEAP_GROW_STEP > self.mapped {
+            return;
+        }
+        self.map.shrink(keep);
+        unsafe { self.pages.unmap(self.buffer.add(keep), self.mapped - keep) };
+        self.mapped = keep;
+    }
+
+    fn grow(&mut self, additional: usize) -> bool {
+        let additional = page_align_up(additional);
+        if self.mapped + additional > self.reserved {
+            return false;
+        }
+        unsafe {
+            if !self.pages.map(self.buffer.add(self.mapped), additional) {
+                return false;
+            }
+            self.map.grow(additional);
+        }
+        self.mapped += additional;
+        true
+    }
+}
+
+fn page_align_up(len: usize) -> usize {
+    len.div_ceil(HEAP_PAGE_SIZE) * HEAP_PAGE_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alloc_sys::heap::{GrowableHeap, PageProvider, HEAP_GROW_STEP};
+    use alloc::alloc::alloc;
+    use core::alloc::Layout;
+    use core::mem::ManuallyDrop;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    const RESERVED_SIZE: usize = 4 * 1024 * 1024;
+
+    /// Pretends to map pages inside a buffer that is already allocated, up to a limit.
+    struct TestPages {
+        mapped: AtomicUsize,
+        limit: usize,
+    }
+
+    impl PageProvider for TestPages {
+        unsafe fn map(&self, _ptr: NonNull<u8>, len: usize) -> bool {
+            if self.mapped.load(Ordering::Relaxed) + len > self.limit {
+                return false;
+            }
+            self.mapped.fetch_add(len, Ordering::Relaxed);
+            true
+        }
+
+        unsafe fn unmap(&self, _ptr: NonNull<u8>, len: usize) {
+            self.mapped.fetch_sub(len, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn heap_grow_shrink_test() {
+        static PAGES: TestPages = TestPages {
+            mapped: AtomicUsize::new(0),
+            limit: 2 * 1024 * 1024,
+        };
+        unsafe {
+            let layout = Layout::from_size_align(RESERVED_SIZE, 4096).unwrap();
+            let ptr = NonNull::new(alloc(layout)).unwrap();
+            // Do not drop the heap, its buffers are not owned by the global allocator
+            let mut heap =
+                ManuallyDrop::new(GrowableHeap::new(ptr, RESERVED_SIZE, &PAGES).unwrap());
+            assert_eq!(heap.capacity(), HEAP_GROW_STEP);
+
+            let first = heap.alloc(HEAP_GROW_STEP / 2, 16, true).unwrap();
+            let second = heap.alloc(HEAP_GROW_STEP, 16, false).unwrap();
+            assert!(heap.contains(first) && heap.contains(second));
+            assert!(heap.capacity() > HEAP_GROW_STEP);
+            let grown_capacity = heap.capacity();
+            let big = heap.alloc(4 * HEAP_GROW_STEP, 4096, false).unwrap();
+            assert_eq!(big as usize % 4096, 0);
+            assert!(heap.capacity() > grown_capacity);
+
+            // Running out of pages fails the allocation instead of panicking
+            assert!(heap.alloc(PAGES.limit, 16, false).is_none());
+
+            heap.dealloc(big);
+            heap.dealloc(second);
+            heap.dealloc(first);
+            assert!(heap.capacity() < 2 * HEAP_GROW_STEP);
+            assert_eq!(PAGES.mapped.load(Ordering::Relaxed), heap.capacity());
+        }
+    }
+}
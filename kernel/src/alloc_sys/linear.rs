@@ -0,0 +1,266 @@
+//! The first version of `MemoryMap`, which keeps its blocks in a sorted list and searches it
+//! linearly. It is only kept to benchmark the current allocator against it.
+
+use crate::alloc_sys::block::{MemoryBlock, MEMORY_BLOCK_ALIGN};
+use crate::utils::heap_array::HeapArray;
+use crate::utils::heap_vec::HeapVec;
+use crate::utils::non_zero_rem::NonZeroRem;
+use alloc::alloc::Global;
+use core::mem;
+use core::num::NonZeroUsize;
+use core::ptr::NonNull;
+
+const MAX_ALIGN: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(4096) };
+const BLOCKS_BUFFER_FRACTION: f64 = 0.1;
+
+pub struct LinearMemoryMap {
+    buffer: HeapArray<u8>,
+    blocks: HeapVec<MemoryBlock>,
+}
+
+impl LinearMemoryMap {
+    // TODO: Clean up this mess.
+    pub fn new(ptr: NonNull<u8>, len: usize) -> Self {
+        let ptr_addr = ptr.addr();
+        let aligned_diff = MAX_ALIGN.get() - (ptr_addr.non_zero_rem(MAX_ALIGN)).get();
+        let aligned_ptr_addr = ptr_addr
+            .checked_add(aligned_diff)
+            .expect("Overflowed usize when calculating aligned LinearMemoryMap base pointer");
+        assert!(
+            aligned_ptr_addr.get() < (ptr_addr.get() + len),
+            "Insufficient size for ALLOCATOR"
+        );
+        let len_aligned = len - aligned_diff;
+        let aligned_ptr = NonNull::dangling().with_addr(aligned_ptr_addr);
+        let blocks_bytes_len = (BLOCKS_BUFFER_FRACTION * (len_aligned as f64)) as usize;
+        let (blocks_ptr, blocks_aligned_bytes_len) = {
+            let ptr_unaligned = unsafe { aligned_ptr.add(len_aligned - blocks_bytes_len) };
+            let ptr_unaligned_addr = ptr_unaligned.addr();
+            let ptr_alignment_diff = MEMORY_BLOCK_ALIGN.get()
+                - (ptr_unaligned_addr.non_zero_rem(MEMORY_BLOCK_ALIGN)).get();
+            let ptr_aligned_addr = ptr_unaligned_addr
+                .checked_add(ptr_alignment_diff)
+                .expect("Overflowed usize when calculating aligned LinearMemoryMap blocks pointer");
+            assert!(
+                aligned_ptr_addr.get() < (ptr_addr.get() + len),
+                "Insufficient size for ALLOCATOR blocks"
+            );
+            (
+                NonNull::dangling().with_addr(ptr_aligned_addr),
+                blocks_bytes_len - ptr_alignment_diff,
+            )
+        };
+        Self {
+            buffer: HeapArray::new_with_ptr(aligned_ptr, len_aligned - blocks_bytes_len, Global),
+            blocks: HeapVec::new_with_ptr(
+                blocks_ptr,
+                blocks_aligned_bytes_len / mem::size_of::<MemoryBlock>(),
+                Global,
+            ),
+        }
+    }
+
+    /// Number of bytes available for allocations.
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let start = self.buffer.as_ptr();
+        ptr >= start && ptr < start.wrapping_add(self.buffer.len())
+    }
+
+    pub unsafe fn alloc(&mut self, size: usize, align: usize, zeroed: bool) -> Option<*mut u8> {
+        if size >= self.buffer.len() || align > MAX_ALIGN.get() {
+            return None;
+        }
+
+        let padded_size = size + align - 1;
+        let block = if let Some(block) = self.blocks.last() {
+            let end = block.end();
+            let free_space = self.buffer.len() - end;
+            if free_space >= padded_size {
+                let start = end + align - (end % align);
+                MemoryBlock::new(start, size)
+            } else {
+                if self.blocks.first().unwrap().start >= padded_size {
+                    MemoryBlock::new(0, size)
+                } else if (self.buffer.len() - self.blocks.last().unwrap().end()) >= padded_size {
+                    let end = self.blocks.last().unwrap().end();
+                    let start = end + align - (end % align);
+                    MemoryBlock::new(start, size)
+                } else {
+                    let mut block = None;
+                    for block_pair in self.blocks.windows(2) {
+                        let inter_size = block_pair[1].start - block_pair[0].end();
+                        if inter_size >= padded_size {
+                            let end = block_pair[0].end();
+                            let start = end + align - (end % align);
+                            block = Some(MemoryBlock::new(start, size));
+                        }
+                    }
+                    // The arena is full, let the caller try somewhere else
+                    block?
+                }
+            }
+        } else {
+            MemoryBlock::new(0, size)
+        };
+
+        self.insert_block(block);
+        if zeroed {
+            for i in block.start..block.end() {
+                self.buffer[i] = 0;
+            }
+        }
+        Some(self.ptr_for_start(block.start))
+    }
+
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        if let Some(start) = self.start_for_ptr(ptr) {
+            self.remove_block(MemoryBlock::new(start, 0));
+        }
+    }
+    pub unsafe fn realloc(&mut self, ptr: *mut u8, size: usize, align: usize) -> Option<*mut u8> {
+        let start = self.start_for_ptr(ptr)?;
+        let pos = self.blocks.iter().position(|block| block.start == start)?;
+        let free_space_end = if let Some(next_block) = self.blocks.get(pos + 1) {
+            next_block.start
+        } else {
+            self.buffer.len()
+        };
+        let block = self.blocks.get(pos)?;
+        let block_size = block.size;
+        if free_space_end >= size {
+            let new_ptr = self.ptr_for_start(block.start);
+            let blocks_mut = self.blocks.get_mut(pos)?;
+            blocks_mut.size = size;
+            Some(new_ptr)
+        } else {
+            let new_ptr = self.alloc(size, align, false)?;
+            new_ptr.copy_from(ptr, block_size);
+            self.dealloc(ptr);
+            Some(new_ptr)
+        }
+    }
+
+    unsafe fn ptr_for_start(&mut self, start: usize) -> *mut u8 {
+        assert!(start < self.buffer.len());
+        self.buffer.as_mut_ptr().add(start)
+    }
+
+    unsafe fn start_for_ptr(&self, ptr: *const u8) -> Option<usize> {
+        if ptr < self.buffer.as_ptr() {
+            return None;
+        }
+        Some(ptr.sub(self.buffer.as_ptr() as usize) as usize)
+    }
+
+    fn insert_block(&mut self, block: MemoryBlock) {
+        let position = match self.blocks.binary_search(&block) {
+            Ok(_) => panic!("Cannot allocate the another block to the same pointer"),
+            Err(i) => i,
+        };
+        self.blocks.insert(position, block);
+    }
+
+    fn remove_block(&mut self, block: MemoryBlock) {
+        let position = match self.blocks.binary_search(&block) {
+            Ok(i) => i,
+            Err(_) => return,
+        };
+        self.blocks.remove(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alloc_sys::linear::LinearMemoryMap;
+    use crate::alloc_sys::map::MemoryMap;
+    use alloc::alloc::alloc;
+    use core::alloc::Layout;
+    use core::mem::ManuallyDrop;
+    use core::ptr::{null_mut, NonNull};
+    use std::time::{Duration, Instant};
+
+    const ARENA_SIZE: usize = 64 * 1024 * 1024;
+    const LIVE_BLOCKS: usize = 4_000;
+    const ROUNDS: usize = 20_000;
+
+    trait Allocator {
+        unsafe fn alloc(&mut self, size: usize) -> *mut u8;
+        unsafe fn dealloc(&mut self, ptr: *mut u8);
+    }
+
+    impl Allocator for LinearMemoryMap {
+        unsafe fn alloc(&mut self, size: usize) -> *mut u8 {
+            self.alloc(size, 16, false).unwrap_or(null_mut())
+        }
+
+        unsafe fn dealloc(&mut self, ptr: *mut u8) {
+            self.dealloc(ptr)
+        }
+    }
+
+    impl Allocator for MemoryMap {
+        unsafe fn alloc(&mut self, size: usize) -> *mut u8 {
+            self.alloc(size, 16, false).unwrap_or(null_mut())
+        }
+
+        unsafe fn dealloc(&mut self, ptr: *mut u8) {
+            self.dealloc(ptr)
+        }
+    }
+
+    /// Keeps `LIVE_BLOCKS` blocks of pseudo random sizes alive, replacing a random one on each
+    /// round, and returns how long it took.
+    unsafe fn run_workload(allocator: &mut impl Allocator) -> Duration {
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next_random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+
+        let start = Instant::now();
+        let mut live = [null_mut(); LIVE_BLOCKS];
+        for ptr in live.iter_mut() {
+            *ptr = allocator.alloc(8 + next_random() % 2048);
+            assert!(!ptr.is_null());
+        }
+        for _ in 0..ROUNDS {
+            let index = next_random() % LIVE_BLOCKS;
+            allocator.dealloc(live[index]);
+            live[index] = allocator.alloc(8 + next_random() % 2048);
+            assert!(!live[index].is_null());
+        }
+        for ptr in live {
+            allocator.dealloc(ptr);
+        }
+        start.elapsed()
+    }
+
+    fn arena() -> NonNull<u8> {
+        let layout = Layout::from_size_align(ARENA_SIZE, 4096).unwrap();
+        NonNull::new(unsafe { alloc(layout) }).unwrap()
+    }
+
+    /// Run with `cargo test bench_against_linear_map -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_against_linear_map() {
+        unsafe {
+            // Do not drop the linear map, its buffer is not owned by the global allocator
+            let mut linear = ManuallyDrop::new(LinearMemoryMap::new(arena(), ARENA_SIZE));
+            let linear_time = run_workload(&mut *linear);
+            let map_time = run_workload(&mut MemoryMap::new(arena(), ARENA_SIZE));
+            println!(
+                "{LIVE_BLOCKS} live blocks, {ROUNDS} rounds: linear {linear_time:?}, \
+                 size classes {map_time:?} ({:.1}x faster)",
+                linear_time.as_secs_f64() / map_time.as_secs_f64()
+            );
+            assert!(map_time < linear_time);
+        }
+    }
+}
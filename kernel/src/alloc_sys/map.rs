@@ -1,167 +1,650 @@
-use crate::alloc_sys::block::{MemoryBlock, MEMORY_BLOCK_ALIGN};
-use crate::utils::heap_array::HeapArray;
-use crate::utils::heap_vec::HeapVec;
-use crate::utils::non_zero_rem::NonZeroRem;
-use core::mem;
+use crate::alloc_sys::block::MemoryBlock;
+use crate::alloc_sys::stats::AllocatorStats;
+#[cfg(feature = "alloc-tracking")]
+use crate::alloc_sys::tracking::AllocSite;
+use core::mem::size_of;
 use core::num::NonZeroUsize;
-use core::ptr::NonNull;
+use core::ptr::{null_mut, NonNull};
 
-pub const MAX_ALIGN: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(4096) };
-pub const BLOCKS_BUFFER_FRACTION: f64 = 0.1;
+/// Big enough for the slabs of the slab caches, which are aligned to their size.
+pub const MAX_ALIGN: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(16 * 1024) };
+/// Every chunk starts at a multiple of this, so every allocation is aligned to it for free.
+pub const MIN_ALIGN: usize = 16;
 
+const HEADER_SIZE: usize = size_of::<ChunkHeader>();
+/// A free chunk has to fit its header and the links of its free list.
+const MIN_CHUNK_SIZE: usize = HEADER_SIZE + size_of::<FreeLinks>();
+const USED: usize = 1;
+
+/// Bytes after every payload that must keep their pattern until the block is freed.
+const RED_ZONE_SIZE: usize = if cfg!(feature = "heap-debug") { 16 } else { 0 };
+#[cfg(feature = "heap-debug")]
+const RED_ZONE_BYTE: u8 = 0xfd;
+#[cfg(feature = "heap-debug")]
+const FRONT_GUARD: usize = usize::from_ne_bytes([RED_ZONE_BYTE; size_of::<usize>()]);
+/// Freed payloads are filled with this, so that reading them after free stands out.
+#[cfg(feature = "heap-debug")]
+const POISON_BYTE: u8 = 0xdd;
+
+/// Each power of two is split into this many size classes.
+const SL_BITS: u32 = 4;
+const SL_COUNT: usize = 1 << SL_BITS;
+/// Chunks smaller than this get one exact size class per multiple of `MIN_ALIGN`.
+const SMALL_CHUNK_SIZE: usize = SL_COUNT * MIN_ALIGN;
+const FL_SHIFT: u32 = SMALL_CHUNK_SIZE.trailing_zeros();
+const FL_COUNT: usize = (usize::BITS - FL_SHIFT + 1) as usize;
+
+/// Sits right before every chunk. The chunks of the buffer follow each other, and the buffer ends
+/// with a used header with no payload, so that the last chunk always has a next one.
+#[repr(C, align(16))]
+struct ChunkHeader {
+    /// Size of the chunk right before this one, or 0 for the first chunk.
+    prev_size: usize,
+    /// Size of this chunk, header included, with the `USED` bit.
+    size: usize,
+    /// Where the chunk was allocated, if it is used.
+    #[cfg(feature = "alloc-tracking")]
+    site: AllocSite,
+    /// Size the block was allocated with. The rest of the payload is a red zone.
+    #[cfg(feature = "heap-debug")]
+    requested: usize,
+    /// Red zone before the payload.
+    #[cfg(feature = "heap-debug")]
+    guard: usize,
+}
+
+/// Stored in the payload of free chunks.
+#[repr(C)]
+struct FreeLinks {
+    prev: *mut ChunkHeader,
+    next: *mut ChunkHeader,
+}
+
+/// Allocates from a single buffer, using the chunk headers as boundary tags.
+///
+/// Free chunks are kept in segregated free lists: one per multiple of 16 bytes for small chunks,
+/// and 16 per power of two for the rest. Two bitmaps track which lists are non-empty, so that
+/// finding a big enough chunk, splitting it and coalescing it back on free are O(1).
 pub struct MemoryMap {
-    buffer: HeapArray<u8>,
-    blocks: HeapVec<MemoryBlock>,
+    base: NonNull<u8>,
+    len: usize,
+    fl_bitmap: usize,
+    sl_bitmaps: [u16; FL_COUNT],
+    free_lists: [[*mut ChunkHeader; SL_COUNT]; FL_COUNT],
+    used_bytes: usize,
+    peak_used_bytes: usize,
+    live_blocks: usize,
 }
 
+// The map owns its buffer, the raw pointers never leave it.
+unsafe impl Send for MemoryMap {}
+
 impl MemoryMap {
-    // TODO: Clean up this mess.
     pub fn new(ptr: NonNull<u8>, len: usize) -> Self {
-        let ptr_addr = ptr.addr();
-        let aligned_diff = MAX_ALIGN.get() - (ptr_addr.non_zero_rem(MAX_ALIGN)).get();
-        let aligned_ptr_addr = ptr_addr
-            .checked_add(aligned_diff)
-            .expect("Overflowed usize when calculating aligned MemoryMap base pointer");
+        let align_offset = ptr.as_ptr().align_offset(MIN_ALIGN);
+        let len = len.saturating_sub(align_offset) & !(MIN_ALIGN - 1);
         assert!(
-            aligned_ptr_addr.get() < (ptr_addr.get() + len),
+            len >= MIN_CHUNK_SIZE + HEADER_SIZE,
             "Insufficient size for ALLOCATOR"
         );
-        let len_aligned = len - aligned_diff;
-        let aligned_ptr = NonNull::dangling().with_addr(aligned_ptr_addr);
-        let blocks_bytes_len = (BLOCKS_BUFFER_FRACTION * (len_aligned as f64)) as usize;
-        let (blocks_ptr, blocks_aligned_bytes_len) = {
-            let ptr_unaligned = unsafe { aligned_ptr.add(len_aligned - blocks_bytes_len) };
-            let ptr_unaligned_addr = ptr_unaligned.addr();
-            let ptr_alignment_diff = MEMORY_BLOCK_ALIGN.get()
-                - (ptr_unaligned_addr.non_zero_rem(MEMORY_BLOCK_ALIGN)).get();
-            let ptr_aligned_addr = ptr_unaligned_addr
-                .checked_add(ptr_alignment_diff)
-                .expect("Overflowed usize when calculating aligned MemoryMap blocks pointer");
-            assert!(
-                aligned_ptr_addr.get() < (ptr_addr.get() + len),
-                "Insufficient size for ALLOCATOR blocks"
-            );
-            (
-                NonNull::dangling().with_addr(ptr_aligned_addr),
-                blocks_bytes_len - ptr_alignment_diff,
-            )
+        let mut map = Self {
+            base: unsafe { ptr.add(align_offset) },
+            len,
+            fl_bitmap: 0,
+            sl_bitmaps: [0; FL_COUNT],
+            free_lists: [[null_mut(); SL_COUNT]; FL_COUNT],
+            used_bytes: 0,
+            peak_used_bytes: 0,
+            live_blocks: 0,
         };
-        Self {
-            buffer: HeapArray::new_with_ptr(aligned_ptr, len_aligned - blocks_bytes_len),
-            blocks: HeapVec::new_with_ptr(
-                blocks_ptr,
-                blocks_aligned_bytes_len / mem::size_of::<MemoryBlock>(),
-            ),
+        unsafe {
+            let chunk = map.chunk_at(0);
+            (*chunk).prev_size = 0;
+            map.write_end_header(len - HEADER_SIZE, len - HEADER_SIZE);
+            set_chunk(chunk, len - HEADER_SIZE, false);
+            map.insert_free(chunk);
         }
+        map
     }
 
-    pub unsafe fn alloc(&mut self, size: usize, align: usize, zeroed: bool) -> Option<*mut u8> {
-        if size >= self.buffer.len() || align > MAX_ALIGN.get() {
-            return None;
+    /// Number of bytes managed by the map, chunk headers included.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        let start = self.base.as_ptr() as *const u8;
+        ptr >= start && ptr < start.wrapping_add(self.len)
+    }
+
+    /// Bytes taken by the allocated blocks, headers included.
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            capacity: self.len,
+            used_bytes: self.used_bytes,
+            free_bytes: self.len - HEADER_SIZE - self.used_bytes,
+            live_blocks: self.live_blocks,
+            largest_free_block: self.largest_free_block(),
+            peak_used_bytes: self.peak_used_bytes,
+        }
+    }
+
+    /// Payload size of the biggest free chunk. Only the free list of the biggest size class
+    /// has to be searched.
+    pub fn largest_free_block(&self) -> usize {
+        if self.fl_bitmap == 0 {
+            return 0;
+        }
+        let fl = self.fl_bitmap.ilog2() as usize;
+        let sl = self.sl_bitmaps[fl].ilog2() as usize;
+        let mut largest = 0;
+        let mut chunk = self.free_lists[fl][sl];
+        while !chunk.is_null() {
+            unsafe {
+                largest = largest.max(chunk_size(chunk));
+                chunk = (*links_of(chunk)).next;
+            }
+        }
+        largest - HEADER_SIZE
+    }
+
+    /// Length the buffer can be shrunk to, as everything after it is free.
+    pub fn used_len(&self) -> usize {
+        unsafe {
+            let end = self.chunk_at(self.len - HEADER_SIZE);
+            if (*end).prev_size == 0 {
+                return self.len;
+            }
+            let last = prev_chunk(end);
+            if is_used(last) {
+                self.len
+            } else {
+                self.offset_of(last) + HEADER_SIZE
+            }
         }
+    }
 
-        let padded_size = size + align - 1;
-        let block = if let Some(block) = self.blocks.last() {
-            let end = block.end();
-            let free_space = self.buffer.len() - end;
-            if free_space >= padded_size {
-                let start = end + align - (end % align);
-                MemoryBlock::new(start, size)
+    /// Size of the free chunk that can serve an allocation of `size` bytes aligned to `align`.
+    /// Growing the buffer by this much is always enough to serve it.
+    pub fn required_chunk_size(size: usize, align: usize) -> Option<usize> {
+        let chunk_size = chunk_size_for(size)?;
+        if align <= MIN_ALIGN {
+            return round_up_to_class(chunk_size);
+        }
+        // Room to move the payload forward to the alignment, leaving a free chunk behind it
+        round_up_to_class(chunk_size.checked_add(align + MIN_CHUNK_SIZE)?)
+    }
+
+    /// Extends the buffer by `additional` bytes, which must be a multiple of [`MIN_ALIGN`].
+    ///
+    /// # Safety
+    /// The `additional` bytes right after the buffer must be valid memory.
+    pub unsafe fn grow(&mut self, additional: usize) {
+        assert!(additional.is_multiple_of(MIN_ALIGN) && additional >= MIN_CHUNK_SIZE);
+        // The old end header becomes the header of the new chunk
+        let chunk = self.chunk_at(self.len - HEADER_SIZE);
+        self.len += additional;
+        self.write_end_header(self.len - HEADER_SIZE, additional);
+        set_chunk(chunk, additional, true);
+        self.free_chunk(chunk);
+    }
+
+    /// Cuts the buffer down to `len` bytes. Only the free space after [`MemoryMap::used_len`] can
+    /// be cut, and what is left of it must either be nothing or fit a whole chunk.
+    pub fn shrink(&mut self, len: usize) {
+        let used_len = self.used_len();
+        assert!(
+            len == used_len || (len >= used_len + MIN_CHUNK_SIZE && len <= self.len),
+            "Cannot shrink over allocated blocks"
+        );
+        assert!(len.is_multiple_of(MIN_ALIGN));
+        if len == self.len {
+            return;
+        }
+        unsafe {
+            let last = prev_chunk(self.chunk_at(self.len - HEADER_SIZE));
+            self.remove_free(last);
+            self.len = len;
+            let end_offset = len - HEADER_SIZE;
+            let last_offset = self.offset_of(last);
+            if end_offset == last_offset {
+                self.write_end_header(end_offset, (*last).prev_size);
             } else {
-                if self.blocks.first().unwrap().start >= padded_size {
-                    MemoryBlock::new(0, size)
-                } else if (self.buffer.len() - self.blocks.last().unwrap().end()) >= padded_size {
-                    let end = self.blocks.last().unwrap().end();
-                    let start = end + align - (end % align);
-                    MemoryBlock::new(start, size)
+                self.write_end_header(end_offset, end_offset - last_offset);
+                set_chunk(last, end_offset - last_offset, false);
+                self.insert_free(last);
+            }
+        }
+    }
+
+    /// Walks every chunk of the buffer, in address order.
+    pub fn blocks(&self) -> impl Iterator<Item = MemoryBlock> + '_ {
+        let end = self.len - HEADER_SIZE;
+        let mut offset = 0;
+        core::iter::from_fn(move || {
+            if offset >= end {
+                return None;
+            }
+            unsafe {
+                let chunk = self.chunk_at(offset);
+                let mut block =
+                    MemoryBlock::new(offset + HEADER_SIZE, chunk_size(chunk) - HEADER_SIZE);
+                block.is_active = is_used(chunk);
+                #[cfg(feature = "alloc-tracking")]
+                if block.is_active {
+                    block.site = (*chunk).site;
+                }
+                offset += chunk_size(chunk);
+                Some(block)
+            }
+        })
+    }
+
+    /// Panics if the chunks do not tile the buffer, if two free chunks are next to each other, or
+    /// if the free lists, the bitmaps or the counters disagree with the chunks. This walks the
+    /// whole buffer, so it is only meant for tests.
+    pub fn check_consistency(&self) {
+        let end = self.len - HEADER_SIZE;
+        let (mut offset, mut prev_size, mut prev_free) = (0, 0, false);
+        let (mut used_bytes, mut live_blocks, mut free_chunks) = (0, 0, 0);
+        while offset < end {
+            unsafe {
+                let chunk = self.chunk_at(offset);
+                let size = chunk_size(chunk);
+                assert_eq!(
+                    (*chunk).prev_size,
+                    prev_size,
+                    "Bad back link at {offset:#x}"
+                );
+                assert!(
+                    size >= MIN_CHUNK_SIZE && size.is_multiple_of(MIN_ALIGN),
+                    "Bad size {size:#x} at {offset:#x}"
+                );
+                assert!(
+                    offset + size <= end,
+                    "Chunk at {offset:#x} overruns the buffer"
+                );
+                if is_used(chunk) {
+                    used_bytes += size;
+                    live_blocks += 1;
                 } else {
-                    let mut block = MemoryBlock::null();
-                    for block_pair in self.blocks.windows(2) {
-                        let inter_size = block_pair[1].start - block_pair[0].end();
-                        if inter_size >= padded_size {
-                            let end = block_pair[0].end();
-                            let start = end + align - (end % align);
-                            block = MemoryBlock::new(start, size);
-                        }
+                    assert!(!prev_free, "Free chunks not merged at {offset:#x}");
+                    let (fl, sl) = size_class(size);
+                    assert!(
+                        self.sl_bitmaps[fl] & (1 << sl) != 0,
+                        "Chunk at {offset:#x} is not in its free list"
+                    );
+                    free_chunks += 1;
+                }
+                prev_free = !is_used(chunk);
+                prev_size = size;
+                offset += size;
+            }
+        }
+        assert_eq!(offset, end, "The chunks do not end at the end header");
+        unsafe {
+            let end_chunk = self.chunk_at(end);
+            assert_eq!((*end_chunk).size, HEADER_SIZE | USED, "Bad end header");
+            assert_eq!(
+                (*end_chunk).prev_size,
+                prev_size,
+                "Bad back link at the end"
+            );
+        }
+        assert_eq!(used_bytes, self.used_bytes);
+        assert_eq!(live_blocks, self.live_blocks);
+
+        let mut listed_chunks = 0;
+        for fl in 0..FL_COUNT {
+            assert_eq!(self.fl_bitmap & (1 << fl) != 0, self.sl_bitmaps[fl] != 0);
+            for sl in 0..SL_COUNT {
+                let mut chunk = self.free_lists[fl][sl];
+                assert_eq!(self.sl_bitmaps[fl] & (1 << sl) != 0, !chunk.is_null());
+                let mut prev = null_mut();
+                while !chunk.is_null() {
+                    unsafe {
+                        assert!(self.contains(chunk.cast()) && !is_used(chunk));
+                        assert_eq!(size_class(chunk_size(chunk)), (fl, sl));
+                        assert_eq!((*links_of(chunk)).prev, prev);
+                        prev = chunk;
+                        chunk = (*links_of(chunk)).next;
                     }
-                    block
+                    listed_chunks += 1;
                 }
             }
-        } else {
-            MemoryBlock::new(0, size)
-        };
+        }
+        assert_eq!(
+            listed_chunks, free_chunks,
+            "The free lists do not hold every free chunk"
+        );
+    }
+
+    pub unsafe fn alloc(&mut self, size: usize, align: usize, zeroed: bool) -> Option<*mut u8> {
+        if align > MAX_ALIGN.get() {
+            return None;
+        }
+        let needed = chunk_size_for(size)?;
+        let search_size = Self::required_chunk_size(size, align)?;
+        if search_size > self.len {
+            return None;
+        }
 
-        self.insert_block(block);
+        let mut chunk = self.find_free(search_size)?;
+        self.remove_free(chunk);
+        let payload = payload_of(chunk);
+        if !payload.addr().is_multiple_of(align) {
+            // Give the start of the chunk back, it is at least a whole chunk long
+            let aligned = (payload.addr() + MIN_CHUNK_SIZE).next_multiple_of(align);
+            let gap = aligned - payload.addr();
+            let rest = chunk.byte_add(gap);
+            (*rest).prev_size = gap;
+            set_chunk(rest, chunk_size(chunk) - gap, false);
+            set_chunk(chunk, gap, false);
+            self.insert_free(chunk);
+            chunk = rest;
+        }
+        set_used(chunk, true);
+        self.split(chunk, needed);
+        self.live_blocks += 1;
+        self.add_used_bytes(chunk_size(chunk) as isize);
+        #[cfg(feature = "alloc-tracking")]
+        {
+            (*chunk).site = AllocSite::EMPTY;
+        }
+
+        let payload = payload_of(chunk);
         if zeroed {
-            for i in block.start..block.end() {
-                self.buffer[i] = 0;
-            }
+            payload.write_bytes(0, chunk_size(chunk) - HEADER_SIZE);
         }
-        Some(self.ptr_for_start(block.start))
+        #[cfg(feature = "heap-debug")]
+        arm_red_zones(chunk, size);
+        Some(payload)
     }
 
     pub unsafe fn dealloc(&mut self, ptr: *mut u8) {
-        if let Some(start) = self.start_for_ptr(ptr) {
-            self.remove_block(MemoryBlock::new(start, 0));
+        #[cfg(feature = "heap-debug")]
+        {
+            self.check_block(ptr);
+            ptr.write_bytes(POISON_BYTE, chunk_size(header_of(ptr)) - HEADER_SIZE);
         }
+        let chunk = header_of(ptr);
+        debug_assert!(is_used(chunk), "Double free of {ptr:p}");
+        self.live_blocks -= 1;
+        self.add_used_bytes(-(chunk_size(chunk) as isize));
+        self.free_chunk(chunk);
     }
+
+    /// Records where the block at `ptr` was allocated.
+    #[cfg(feature = "alloc-tracking")]
+    pub unsafe fn set_site(&mut self, ptr: *mut u8, site: AllocSite) {
+        (*header_of(ptr)).site = site;
+    }
+
+    /// Resizes the block at `ptr`, which must have been allocated with the same `align`.
+    ///
+    /// Shrinking always happens in place. Growing happens in place if the chunk after the block is
+    /// free and big enough to take the difference, and moves the block to a chunk aligned to
+    /// `align` otherwise. Returns `None`, leaving the block untouched, if it does not fit anywhere.
     pub unsafe fn realloc(&mut self, ptr: *mut u8, size: usize, align: usize) -> Option<*mut u8> {
-        let start = self.start_for_ptr(ptr)?;
-        let pos = self.blocks.iter().position(|block| block.start == start)?;
-        let free_space_end = if let Some(next_block) = self.blocks.get(pos + 1) {
-            next_block.start
+        #[cfg(feature = "heap-debug")]
+        self.check_block(ptr);
+        debug_assert!(
+            ptr.addr().is_multiple_of(align),
+            "{ptr:p} is not aligned to {align}, realloc cannot change the alignment of a block"
+        );
+        let chunk = header_of(ptr);
+        let needed = chunk_size_for(size)?;
+        let current = chunk_size(chunk);
+        if needed <= current {
+            self.split(chunk, needed);
         } else {
-            self.buffer.len()
+            let next = next_chunk(chunk);
+            if is_used(next) || current + chunk_size(next) < needed {
+                let new_ptr = self.alloc(size, align, false)?;
+                new_ptr.copy_from_nonoverlapping(ptr, (current - HEADER_SIZE).min(size));
+                self.dealloc(ptr);
+                return Some(new_ptr);
+            }
+            self.remove_free(next);
+            set_chunk(chunk, current + chunk_size(next), true);
+            self.split(chunk, needed);
+        }
+        self.add_used_bytes(chunk_size(chunk) as isize - current as isize);
+        #[cfg(feature = "heap-debug")]
+        arm_red_zones(chunk, size);
+        Some(ptr)
+    }
+
+    /// Panics unless `ptr` is a live block of this map with both of its red zones intact.
+    #[cfg(feature = "heap-debug")]
+    unsafe fn check_block(&self, ptr: *mut u8) {
+        assert!(
+            self.contains(ptr),
+            "Heap corruption: freed {ptr:p}, which is not in the heap at {:p} ({} bytes)",
+            self.base,
+            self.len
+        );
+        let offset = ptr.addr() - self.base.addr().get();
+        let Some(block) = self.blocks().find(|block| block.end() > offset) else {
+            panic!("Heap corruption: freed {ptr:p}, which is not in any block");
         };
-        let block = self.blocks.get(pos)?;
-        let block_size = block.size;
-        if free_space_end >= size {
-            let new_ptr = self.ptr_for_start(block.start);
-            let blocks_mut = self.blocks.get_mut(pos)?;
-            blocks_mut.size = size;
-            Some(new_ptr)
-        } else {
-            let new_ptr = self.alloc(size, align, false)?;
-            new_ptr.copy_from(ptr, block_size);
-            self.dealloc(ptr);
-            Some(new_ptr)
+        if block.start != offset {
+            panic!(
+                "Heap corruption: freed {ptr:p}, which is inside the {} block at {:p} ({} bytes)",
+                if block.is_active { "used" } else { "free" },
+                self.base.add(block.start),
+                block.size
+            );
+        }
+        assert!(
+            block.is_active,
+            "Heap corruption: double free of {ptr:p} ({} bytes)",
+            block.size
+        );
+
+        let chunk = header_of(ptr);
+        let requested = (*chunk).requested;
+        let rear_zone = core::slice::from_raw_parts(ptr.add(requested), block.size - requested);
+        if (*chunk).guard != FRONT_GUARD || rear_zone.iter().any(|&byte| byte != RED_ZONE_BYTE) {
+            panic!(
+                "Heap corruption: the red zone of the block at {ptr:p} ({requested} bytes) was \
+                 overwritten"
+            );
         }
     }
 
-    unsafe fn ptr_for_start(&mut self, start: usize) -> *mut u8 {
-        assert!(start < self.buffer.len());
-        self.buffer.as_mut_ptr().add(start)
+    fn add_used_bytes(&mut self, delta: isize) {
+        self.used_bytes = self.used_bytes.wrapping_add_signed(delta);
+        self.peak_used_bytes = self.peak_used_bytes.max(self.used_bytes);
     }
 
-    unsafe fn start_for_ptr(&self, ptr: *const u8) -> Option<usize> {
-        if ptr < self.buffer.as_ptr() {
-            return None;
+    /// Cuts `chunk` down to `size` bytes, and frees the rest if it fits a whole chunk.
+    unsafe fn split(&mut self, chunk: *mut ChunkHeader, size: usize) {
+        let total = chunk_size(chunk);
+        if total < size + MIN_CHUNK_SIZE {
+            return;
         }
-        Some(ptr.sub(self.buffer.as_ptr() as usize) as usize)
+        set_chunk(chunk, size, is_used(chunk));
+        let rest = chunk.byte_add(size);
+        (*rest).prev_size = size;
+        set_chunk(rest, total - size, true);
+        self.free_chunk(rest);
     }
 
-    fn insert_block(&mut self, block: MemoryBlock) {
-        let position = match self.blocks.binary_search(&block) {
-            Ok(_) => panic!("Cannot allocate the another block to the same pointer"),
-            Err(i) => i,
-        };
-        self.blocks.insert(position, block);
+    /// Marks `chunk` as free, merging it with its free neighbours.
+    unsafe fn free_chunk(&mut self, mut chunk: *mut ChunkHeader) {
+        let mut size = chunk_size(chunk);
+        let next = next_chunk(chunk);
+        if !is_used(next) {
+            self.remove_free(next);
+            size += chunk_size(next);
+        }
+        if (*chunk).prev_size != 0 {
+            let prev = prev_chunk(chunk);
+            if !is_used(prev) {
+                self.remove_free(prev);
+                size += chunk_size(prev);
+                chunk = prev;
+            }
+        }
+        set_chunk(chunk, size, false);
+        self.insert_free(chunk);
     }
 
-    fn remove_block(&mut self, block: MemoryBlock) {
-        let position = match self.blocks.binary_search(&block) {
-            Ok(i) => i,
-            Err(_) => return,
+    /// Finds a free chunk of at least `size` bytes, which must already be rounded up to its
+    /// class.
+    fn find_free(&self, size: usize) -> Option<*mut ChunkHeader> {
+        let (fl, sl) = size_class(size);
+        let sl_map = self.sl_bitmaps[fl] & (u16::MAX << sl);
+        let (fl, sl_map) = if sl_map != 0 {
+            (fl, sl_map)
+        } else {
+            let fl_map = self.fl_bitmap & usize::MAX.checked_shl(fl as u32 + 1).unwrap_or(0);
+            if fl_map == 0 {
+                return None;
+            }
+            let fl = fl_map.trailing_zeros() as usize;
+            (fl, self.sl_bitmaps[fl])
         };
-        self.blocks.remove(position);
+        Some(self.free_lists[fl][sl_map.trailing_zeros() as usize])
+    }
+
+    unsafe fn insert_free(&mut self, chunk: *mut ChunkHeader) {
+        let (fl, sl) = size_class(chunk_size(chunk));
+        let head = self.free_lists[fl][sl];
+        let links = links_of(chunk);
+        (*links).prev = null_mut();
+        (*links).next = head;
+        if !head.is_null() {
+            (*links_of(head)).prev = chunk;
+        }
+        self.free_lists[fl][sl] = chunk;
+        self.sl_bitmaps[fl] |= 1 << sl;
+        self.fl_bitmap |= 1 << fl;
+    }
+
+    unsafe fn remove_free(&mut self, chunk: *mut ChunkHeader) {
+        let (fl, sl) = size_class(chunk_size(chunk));
+        let FreeLinks { prev, next } = links_of(chunk).read();
+        if !next.is_null() {
+            (*links_of(next)).prev = prev;
+        }
+        if prev.is_null() {
+            self.free_lists[fl][sl] = next;
+            if next.is_null() {
+                self.sl_bitmaps[fl] &= !(1 << sl);
+                if self.sl_bitmaps[fl] == 0 {
+                    self.fl_bitmap &= !(1 << fl);
+                }
+            }
+        } else {
+            (*links_of(prev)).next = next;
+        }
+    }
+
+    unsafe fn write_end_header(&mut self, offset: usize, prev_size: usize) {
+        let end = self.chunk_at(offset);
+        (*end).prev_size = prev_size;
+        (*end).size = HEADER_SIZE | USED;
+    }
+
+    unsafe fn chunk_at(&self, offset: usize) -> *mut ChunkHeader {
+        self.base.as_ptr().add(offset).cast()
+    }
+
+    fn offset_of(&self, chunk: *mut ChunkHeader) -> usize {
+        chunk.addr() - self.base.addr().get()
+    }
+}
+
+/// Size of a chunk with room for `size` bytes of payload.
+fn chunk_size_for(size: usize) -> Option<usize> {
+    let size = size.checked_add(HEADER_SIZE + RED_ZONE_SIZE + MIN_ALIGN - 1)? & !(MIN_ALIGN - 1);
+    Some(size.max(MIN_CHUNK_SIZE))
+}
+
+/// Fills the red zones around the first `size` bytes of the payload of `chunk`.
+#[cfg(feature = "heap-debug")]
+unsafe fn arm_red_zones(chunk: *mut ChunkHeader, size: usize) {
+    (*chunk).requested = size;
+    (*chunk).guard = FRONT_GUARD;
+    let payload = payload_of(chunk);
+    let payload_size = chunk_size(chunk) - HEADER_SIZE;
+    payload
+        .add(size)
+        .write_bytes(RED_ZONE_BYTE, payload_size - size);
+}
+
+/// First and second level indices of the free list of chunks of `size` bytes.
+fn size_class(size: usize) -> (usize, usize) {
+    if size < SMALL_CHUNK_SIZE {
+        return (0, size / MIN_ALIGN);
     }
+    let log2 = size.ilog2();
+    let sl = (size >> (log2 - SL_BITS)) & (SL_COUNT - 1);
+    ((log2 - FL_SHIFT + 1) as usize, sl)
+}
+
+/// Rounds `size` up so that every chunk of its size class is at least as big.
+fn round_up_to_class(size: usize) -> Option<usize> {
+    if size < SMALL_CHUNK_SIZE {
+        return Some(size);
+    }
+    let step = 1 << (size.ilog2() - SL_BITS);
+    Some(size.checked_add(step - 1)? & !(step - 1))
+}
+
+unsafe fn chunk_size(chunk: *mut ChunkHeader) -> usize {
+    (*chunk).size & !USED
+}
+
+unsafe fn is_used(chunk: *mut ChunkHeader) -> bool {
+    (*chunk).size & USED != 0
+}
+
+unsafe fn set_used(chunk: *mut ChunkHeader, used: bool) {
+    set_chunk(chunk, chunk_size(chunk), used);
+}
+
+/// Writes the size of `chunk`, and updates the back link of the chunk after it.
+unsafe fn set_chunk(chunk: *mut ChunkHeader, size: usize, used: bool) {
+    (*chunk).size = size | if used { USED } else { 0 };
+    (*next_chunk(chunk)).prev_size = size;
+}
+
+unsafe fn next_chunk(chunk: *mut ChunkHeader) -> *mut ChunkHeader {
+    chunk.byte_add(chunk_size(chunk))
+}
+
+unsafe fn prev_chunk(chunk: *mut ChunkHeader) -> *mut ChunkHeader {
+    chunk.byte_sub((*chunk).prev_size)
+}
+
+unsafe fn payload_of(chunk: *mut ChunkHeader) -> *mut u8 {
+    chunk.add(1).cast()
+}
+
+unsafe fn links_of(chunk: *mut ChunkHeader) -> *mut FreeLinks {
+    chunk.add(1).cast()
+}
+
+unsafe fn header_of(ptr: *mut u8) -> *mut ChunkHeader {
+    ptr.cast::<ChunkHeader>().sub(1)
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::alloc_sys::fuzz::{run_sequence, STEP_SIZE};
+    #[cfg(feature = "heap-debug")]
+    use crate::alloc_sys::map::POISON_BYTE;
+    use crate::alloc_sys::map::{chunk_size_for, MemoryMap, HEADER_SIZE};
     use crate::alloc_sys::ALLOCATOR;
     use crate::utils::heap_array::HeapArray;
-    use alloc::alloc::alloc;
+    use alloc::alloc::{alloc, Global};
     use core::alloc::{GlobalAlloc, Layout};
     use core::mem::ManuallyDrop;
     use core::ptr::NonNull;
@@ -171,7 +654,7 @@ mod tests {
     unsafe fn initialize_allocator() {
         let alloc_layout = Layout::from_size_align_unchecked(ALLOCATOR_SIZE, 4096);
         let alloc_ptr = NonNull::new(alloc(alloc_layout)).unwrap();
-        ALLOCATOR.initialize(alloc_ptr, ALLOCATOR_SIZE);
+        ALLOCATOR.add_arena(alloc_ptr, ALLOCATOR_SIZE).unwrap();
     }
 
     #[test]
@@ -180,19 +663,6 @@ mod tests {
             println!();
             initialize_allocator();
             let layout = Layout::from_size_align(50, 16).unwrap();
-            println!(
-                "Buffer ptr:\t{:#018x}",
-                ALLOCATOR
-                    .map
-                    .get()
-                    .as_ref()
-                    .unwrap()
-                    .as_ref()
-                    // .get().unwrap()
-                    .unwrap()
-                    .buffer
-                    .as_ptr() as usize
-            );
             let my_ptr = ALLOCATOR.alloc(layout);
             let is_aligned = if my_ptr as usize % layout.align() == 0 {
                 true
@@ -217,7 +687,7 @@ mod tests {
             let layout = Layout::array::<u8>(ARRAY_LEN).unwrap();
             let ptr = NonNull::new(ALLOCATOR.alloc(layout))
                 .expect("Returned a null pointer from allocator");
-            let _array = HeapArray::new_with_ptr(ptr, ARRAY_LEN);
+            let _array = HeapArray::new_with_ptr(ptr, ARRAY_LEN, Global);
             // Do not drop HeapArray, global_allocator is not our allocator
             let mut array = ManuallyDrop::new(_array);
             for (i, mut byte) in array.iter_mut().enumerate() {
@@ -228,4 +698,235 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn coalesce_test() {
+        unsafe {
+            let layout = Layout::from_size_align(64 * 1024, 4096).unwrap();
+            let mut map = MemoryMap::new(NonNull::new(alloc(layout)).unwrap(), 64 * 1024);
+            let first = map.alloc(100, 16, false).unwrap();
+            let second = map.alloc(5000, 4096, true).unwrap();
+            let third = map.alloc(30, 8, false).unwrap();
+            assert_eq!(second as usize % 4096, 0);
+            assert!(core::slice::from_raw_parts(second, 5000)
+                .iter()
+                .all(|b| *b == 0));
+
+            // Growing into the free chunk after the block keeps it in place
+            map.dealloc(third);
+            assert_eq!(map.realloc(second, 8000, 4096), Some(second));
+            assert_eq!(map.realloc(second, 10, 4096), Some(second));
+
+            map.dealloc(first);
+            map.dealloc(second);
+            let blocks = map.blocks().collect::<Vec<_>>();
+            assert_eq!(blocks.len(), 1);
+            assert!(!blocks[0].is_active);
+            assert_eq!(map.used_len(), HEADER_SIZE);
+            let stats = map.stats();
+            assert_eq!((stats.used_bytes, stats.live_blocks), (0, 0));
+            assert_eq!(stats.largest_free_block, stats.free_bytes - HEADER_SIZE);
+            assert!(stats.peak_used_bytes > 8000);
+        }
+    }
+
+    /// A block of the reference model, filled with bytes counting up from `seed`.
+    struct ModelBlock {
+        ptr: *mut u8,
+        size: usize,
+        align: usize,
+        seed: u8,
+    }
+
+    impl ModelBlock {
+        unsafe fn fill(&self) {
+            for i in 0..self.size {
+                self.ptr.add(i).write(self.seed.wrapping_add(i as u8));
+            }
+        }
+
+        unsafe fn check(&self, len: usize) {
+            for i in 0..len.min(self.size) {
+                assert_eq!(*self.ptr.add(i), self.seed.wrapping_add(i as u8));
+            }
+        }
+    }
+
+    /// xorshift64, so that failures can be replayed from their seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+    }
+
+    /// Whether the map should resize the block at `ptr` to `size` bytes without moving it.
+    fn fits_in_place(map: &MemoryMap, ptr: *mut u8, size: usize) -> bool {
+        let offset = ptr as usize - map.base.as_ptr() as usize;
+        let mut blocks = map.blocks().skip_while(|block| block.start != offset);
+        let block = blocks.next().unwrap();
+        let needed = chunk_size_for(size).unwrap();
+        let available = match blocks.next() {
+            Some(next) if !next.is_active => block.size + next.size + 2 * HEADER_SIZE,
+            _ => block.size + HEADER_SIZE,
+        };
+        needed <= available
+    }
+
+    #[test]
+    fn realloc_model_test() {
+        const MAP_SIZE: usize = 256 * 1024;
+        const ALIGNS: [usize; 6] = [1, 8, 16, 64, 256, 4096];
+        for seed in 1..=20u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            unsafe {
+                let layout = Layout::from_size_align(MAP_SIZE, 4096).unwrap();
+                let mut map = MemoryMap::new(NonNull::new(alloc(layout)).unwrap(), MAP_SIZE);
+                let mut model: Vec<ModelBlock> = Vec::new();
+                for step in 0..2000 {
+                    let size = if rng.below(10) == 0 {
+                        rng.below(16 * 1024)
+                    } else {
+                        rng.below(512)
+                    };
+                    match rng.below(3) {
+                        0 if !model.is_empty() => {
+                            let block = model.swap_remove(rng.below(model.len()));
+                            block.check(block.size);
+                            map.dealloc(block.ptr);
+                        }
+                        1 if !model.is_empty() => {
+                            let index = rng.below(model.len());
+                            let block = &mut model[index];
+                            let in_place = fits_in_place(&map, block.ptr, size);
+                            let Some(ptr) = map.realloc(block.ptr, size, block.align) else {
+                                assert!(!in_place, "Seed {seed}, step {step}: realloc failed");
+                                continue;
+                            };
+                            assert_eq!(ptr == block.ptr, in_place, "Seed {seed}, step {step}");
+                            assert!(size > block.size || ptr == block.ptr);
+                            assert!(ptr.addr().is_multiple_of(block.align));
+                            block.ptr = ptr;
+                            block.check(size);
+                            block.size = size;
+                            block.fill();
+                        }
+                        _ => {
+                            let align = ALIGNS[rng.below(ALIGNS.len())];
+                            if let Some(ptr) = map.alloc(size, align, false) {
+                                assert!(ptr.addr().is_multiple_of(align));
+                                let seed = rng.next() as u8;
+                                let block = ModelBlock {
+                                    ptr,
+                                    size,
+                                    align,
+                                    seed,
+                                };
+                                block.fill();
+                                model.push(block);
+                            }
+                        }
+                    }
+
+                    // No two blocks overlap, and none of them was overwritten
+                    model.sort_by_key(|block| block.ptr);
+                    for pair in model.windows(2) {
+                        assert!(pair[0].ptr.add(pair[0].size) <= pair[1].ptr);
+                    }
+                    if step % 100 == 0 {
+                        model.iter().for_each(|block| block.check(block.size));
+                    }
+                    assert_eq!(map.stats().live_blocks, model.len());
+                }
+                for block in model.drain(..) {
+                    block.check(block.size);
+                    map.dealloc(block.ptr);
+                }
+                assert_eq!(map.used_len(), HEADER_SIZE);
+            }
+        }
+    }
+
+    #[test]
+    fn random_sequence_test() {
+        const MAP_SIZE: usize = 1024 * 1024;
+        for seed in 1..=10u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            let data = (0..2000 * STEP_SIZE)
+                .map(|_| rng.next() as u8)
+                .collect::<Vec<_>>();
+            unsafe {
+                let layout = Layout::from_size_align(MAP_SIZE, 4096).unwrap();
+                let mut map = MemoryMap::new(NonNull::new(alloc(layout)).unwrap(), MAP_SIZE);
+                run_sequence(&mut map, &data);
+            }
+        }
+    }
+
+    #[cfg(feature = "heap-debug")]
+    unsafe fn debug_map() -> MemoryMap {
+        let layout = Layout::from_size_align(64 * 1024, 4096).unwrap();
+        MemoryMap::new(NonNull::new(alloc(layout)).unwrap(), 64 * 1024)
+    }
+
+    #[cfg(feature = "heap-debug")]
+    #[test]
+    fn poison_test() {
+        unsafe {
+            let mut map = debug_map();
+            let first = map.alloc(100, 16, false).unwrap();
+            let second = map.alloc(100, 16, false).unwrap();
+            first.write_bytes(0xaa, 100);
+            let second = map.realloc(second, 200, 16).unwrap();
+            second.write_bytes(0xaa, 200);
+            map.dealloc(second);
+            // The free links are stored at the start of the payload
+            let payload = core::slice::from_raw_parts(first.add(16), 84);
+            map.dealloc(first);
+            assert!(payload.iter().all(|&byte| byte == POISON_BYTE));
+        }
+    }
+
+    #[cfg(feature = "heap-debug")]
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn double_free_test() {
+        unsafe {
+            let mut map = debug_map();
+            let ptr = map.alloc(100, 16, false).unwrap();
+            map.dealloc(ptr);
+            map.dealloc(ptr);
+        }
+    }
+
+    #[cfg(feature = "heap-debug")]
+    #[test]
+    #[should_panic(expected = "inside the used block")]
+    fn foreign_pointer_test() {
+        unsafe {
+            let mut map = debug_map();
+            let ptr = map.alloc(100, 16, false).unwrap();
+            map.dealloc(ptr.add(16));
+        }
+    }
+
+    #[cfg(feature = "heap-debug")]
+    #[test]
+    #[should_panic(expected = "red zone")]
+    fn red_zone_test() {
+        unsafe {
+            let mut map = debug_map();
+            let ptr = map.alloc(100, 16, false).unwrap();
+            ptr.write_bytes(0, 101);
+            map.dealloc(ptr);
+        }
+    }
 }
@@ -1,72 +1,715 @@
+pub mod arena;
 mod block;
+#[cfg(any(test, fuzzing))]
+pub mod fuzz;
+pub mod heap;
+#[cfg(test)]
+mod linear;
 mod map;
+pub mod oom;
+pub mod slab;
+pub mod stats;
+#[cfg(feature = "alloc-tracking")]
+pub mod tracking;
 
+use crate::alloc_sys::block::MemoryBlock;
+use crate::alloc_sys::heap::{GrowableHeap, PageProvider};
 use crate::alloc_sys::map::MemoryMap;
-use core::alloc::{GlobalAlloc, Layout};
-use core::cell::UnsafeCell;
-use core::ptr::{null_mut, NonNull};
+use crate::alloc_sys::slab::{slab_layout, RawSlabCache, SlabStats, SLAB_SIZE};
+use crate::alloc_sys::stats::AllocatorStats;
+#[cfg(feature = "alloc-tracking")]
+use crate::alloc_sys::tracking::AllocSite;
+use crate::logger::logln;
+use crate::sync::SpinLock;
+use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+use core::ptr::{self, null_mut, NonNull};
+
+pub const MAX_ARENAS: usize = 16;
+/// Regions smaller than this are not worth the bookkeeping of a whole arena.
+pub const MIN_ARENA_SIZE: usize = 64 * 1024;
+/// Allocations of up to 2048 bytes are served by slab caches, one per power of two from 16.
+pub const SLAB_CLASSES: usize = 8;
+const MIN_SLAB_OBJECT: usize = 16;
+const MAX_SLAB_OBJECT: usize = MIN_SLAB_OBJECT << (SLAB_CLASSES - 1);
+/// The debugging features keep their data in the header of every block, which slab objects do
+/// not have.
+const SLABS_ENABLED: bool = !cfg!(any(feature = "heap-debug", feature = "alloc-tracking"));
+/// How many blocks are copied out of the allocator at a time to be logged.
+const BLOCK_BATCH: usize = 32;
 
 #[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: SystemAllocator = SystemAllocator::new();
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllocatorError {
+    ArenaTooSmall(usize),
+    TooManyArenas,
+    HeapAlreadyInitialized,
+    OutOfMemory,
+}
+
+/// Hands out memory from several disjoint arenas, one per memory region, and from a heap that
+/// grows on demand.
+///
+/// Allocations are served by the first arena with enough free space, and by the heap once every
+/// arena is full. Small allocations are served by slab caches instead, whose slabs come from the
+/// arenas and the heap in turn.
 pub struct SystemAllocator {
-    map: UnsafeCell<Option<MemoryMap>>,
+    inner: SpinLock<Arenas>,
+}
+
+struct Arenas {
+    arenas: [Option<MemoryMap>; MAX_ARENAS],
+    heap: Option<GrowableHeap>,
+    slabs: [RawSlabCache; SLAB_CLASSES],
+    peak_used_bytes: usize,
 }
 
 impl SystemAllocator {
     pub const fn new() -> Self {
         Self {
-            map: UnsafeCell::new(None),
+            inner: SpinLock::new(Arenas {
+                arenas: [const { None }; MAX_ARENAS],
+                heap: None,
+                slabs: slab_caches(),
+                peak_used_bytes: 0,
+            }),
         }
     }
 
-    pub fn initialize(&self, ptr: NonNull<u8>, len: usize) {
-        unsafe {
-            match self.map.get().as_mut() {
-                Some(Some(_)) => {}
-                Some(opt) => *opt = Some(MemoryMap::new(ptr, len)),
-                None => panic!(
-                    "Error while initializing SystemAllocator. Ptr to SystemAllocator.map is null."
-                ),
+    /// Adds the `len` bytes at `ptr` as a new arena.
+    pub fn add_arena(&self, ptr: NonNull<u8>, len: usize) -> Result<(), AllocatorError> {
+        if len < MIN_ARENA_SIZE {
+            return Err(AllocatorError::ArenaTooSmall(len));
+        }
+        let mut inner = self.inner.lock_irqsave();
+        let slot = inner
+            .arenas
+            .iter_mut()
+            .find(|arena| arena.is_none())
+            .ok_or(AllocatorError::TooManyArenas)?;
+        *slot = Some(MemoryMap::new(ptr, len));
+        Ok(())
+    }
+
+    /// Places the growable heap in the `len` bytes of virtual memory at `ptr`, mapping its pages
+    /// through `pages` as needed.
+    ///
+    /// # Safety
+    /// The range must be reserved for the heap, and must not be mapped yet.
+    pub unsafe fn init_heap(
+        &self,
+        ptr: NonNull<u8>,
+        len: usize,
+        pages: &'static dyn PageProvider,
+    ) -> Result<(), AllocatorError> {
+        let mut inner = self.inner.lock_irqsave();
+        if inner.heap.is_some() {
+            return Err(AllocatorError::HeapAlreadyInitialized);
+        }
+        inner.heap = Some(GrowableHeap::new(ptr, len, pages)?);
+        Ok(())
+    }
+
+    pub fn arena_count(&self) -> usize {
+        self.inner.lock_irqsave().arenas().count()
+    }
+
+    /// Total number of bytes available for allocations across all arenas and the mapped part of
+    /// the heap.
+    pub fn capacity(&self) -> usize {
+        let mut inner = self.inner.lock_irqsave();
+        let heap_capacity = inner.heap.as_ref().map_or(0, |heap| heap.capacity());
+        inner.arenas().map(|arena| arena.capacity()).sum::<usize>() + heap_capacity
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        self.inner.lock_irqsave().stats()
+    }
+
+    /// Runs `alloc` and, if it runs out of memory, gives the empty slabs back and runs the
+    /// shrinkers before trying again. `alloc` must leave everything untouched when it fails.
+    fn alloc_or_shrink(
+        &self,
+        size: usize,
+        mut alloc: impl FnMut(&mut Arenas) -> *mut u8,
+    ) -> *mut u8 {
+        let mut inner = self.inner.lock_irqsave();
+        let mut ptr = alloc(&mut inner);
+        if ptr.is_null() && inner.trim() != 0 {
+            ptr = alloc(&mut inner);
+        }
+        if ptr.is_null() {
+            drop(inner);
+            if oom::run_shrinkers(size) == 0 {
+                return ptr;
+            }
+            inner = self.inner.lock_irqsave();
+            ptr = alloc(&mut inner);
+        }
+        unsafe { inner.track(ptr) }
+    }
+
+    /// Statistics of the slab cache of each size class, from the smallest.
+    pub fn slab_stats(&self) -> [SlabStats; SLAB_CLASSES] {
+        self.inner
+            .lock_irqsave()
+            .slabs
+            .each_ref()
+            .map(|slab| slab.stats())
+    }
+
+    /// Logs every block of every arena and of the heap.
+    pub fn dump_blocks(&self) {
+        logln!("Allocator: {}", self.stats());
+        for stats in self.slab_stats().iter().filter(|stats| stats.slabs != 0) {
+            logln!("Slab cache of {stats}");
+        }
+        let mut current = None;
+        self.for_each_block_unlocked(|name, index, block| {
+            if current != Some((name, index)) {
+                logln!("{name} {index}:");
+                current = Some((name, index));
+            }
+            logln!(
+                "    {:#010x}..{:#010x} {:>10} bytes {}",
+                block.start,
+                block.end(),
+                block.size,
+                if block.is_active { "used" } else { "free" }
+            );
+        });
+    }
+
+    /// Sequence number of the next allocation, to pass to [`SystemAllocator::leak_report`].
+    #[cfg(feature = "alloc-tracking")]
+    pub fn checkpoint(&self) -> u64 {
+        tracking::next_sequence()
+    }
+
+    /// Logs the blocks allocated since `checkpoint` that are still alive, with their callers.
+    /// Returns how many there are.
+    #[cfg(feature = "alloc-tracking")]
+    pub fn leak_report(&self, checkpoint: u64) -> usize {
+        let mut leaks = 0;
+        let mut leaked_bytes = 0;
+        self.for_each_leak(checkpoint, |block| {
+            logln!("Leaked {} bytes: {}", block.size, block.site);
+            leaks += 1;
+            leaked_bytes += block.size;
+        });
+        logln!("{leaks} blocks leaked since #{checkpoint}, {leaked_bytes} bytes in total.");
+        leaks
+    }
+
+    #[cfg(feature = "alloc-tracking")]
+    fn for_each_leak(&self, checkpoint: u64, mut f: impl FnMut(MemoryBlock)) {
+        self.for_each_block_unlocked(|_, _, block| {
+            if block.is_active && block.site.sequence >= checkpoint {
+                f(block);
+            }
+        });
+    }
+
+    /// Calls `f` like [`Arenas::for_each_block`], but with the allocator unlocked, so that `f`
+    /// can allocate, as logging does. The blocks are copied out [`BLOCK_BATCH`] at a time, so a
+    /// block that changes between two batches can be missed or seen twice.
+    fn for_each_block_unlocked(&self, mut f: impl FnMut(&'static str, usize, MemoryBlock)) {
+        let mut batch = [None; BLOCK_BATCH];
+        let mut done = 0;
+        loop {
+            let mut len = 0;
+            let mut seen = 0;
+            self.inner
+                .lock_irqsave()
+                .for_each_block(|name, index, block| {
+                    if seen >= done && len < BLOCK_BATCH {
+                        batch[len] = Some((name, index, block));
+                        len += 1;
+                    }
+                    seen += 1;
+                });
+            for &(name, index, block) in batch[..len].iter().flatten() {
+                f(name, index, block);
+            }
+            if len < BLOCK_BATCH {
+                return;
             }
+            done += len;
         }
     }
 }
 
+impl Arenas {
+    fn arenas(&mut self) -> impl Iterator<Item = &mut MemoryMap> {
+        self.arenas.iter_mut().flatten()
+    }
+
+    fn heap_for_ptr(&mut self, ptr: *const u8) -> Option<&mut GrowableHeap> {
+        self.heap.as_mut().filter(|heap| heap.contains(ptr))
+    }
+
+    fn arena_for_ptr(&mut self, ptr: *const u8) -> Option<&mut MemoryMap> {
+        self.arenas().find(|arena| arena.contains(ptr))
+    }
+
+    fn stats(&mut self) -> AllocatorStats {
+        let heap_stats = self.heap.as_ref().map(|heap| heap.stats());
+        let stats = self
+            .arenas()
+            .map(|arena| arena.stats())
+            .chain(heap_stats)
+            .fold(AllocatorStats::default(), AllocatorStats::merge);
+        AllocatorStats {
+            peak_used_bytes: self.peak_used_bytes,
+            ..stats
+        }
+    }
+
+    fn used_bytes(&mut self) -> usize {
+        let heap_used = self.heap.as_ref().map_or(0, |heap| heap.stats().used_bytes);
+        self.arenas().map(|arena| arena.used_bytes()).sum::<usize>() + heap_used
+    }
+
+    /// Calls `f` with the name and index of the arena or heap holding each block, and with the
+    /// block itself. Block offsets are relative to the start of their arena.
+    fn for_each_block(&mut self, mut f: impl FnMut(&'static str, usize, MemoryBlock)) {
+        for (index, arena) in self.arenas().enumerate() {
+            arena.blocks().for_each(|block| f("Arena", index, block));
+        }
+        if let Some(heap) = self.heap.as_ref() {
+            heap.blocks().for_each(|block| f("Heap", 0, block));
+        }
+    }
+
+    /// Gives the empty slabs kept by the slab caches back to the arenas and the heap. Returns how
+    /// many bytes were released.
+    fn trim(&mut self) -> usize {
+        let mut released = 0;
+        for class in 0..SLAB_CLASSES {
+            while let Some(slab) = self.slabs[class].take_empty_slab() {
+                unsafe { self.dealloc_block(slab.as_ptr()) };
+                released += SLAB_SIZE;
+            }
+        }
+        released
+    }
+
+    /// Marks the block at `ptr` as allocated by the caller, and updates the peak usage.
+    unsafe fn track(&mut self, ptr: *mut u8) -> *mut u8 {
+        if ptr.is_null() {
+            return ptr;
+        }
+        #[cfg(feature = "alloc-tracking")]
+        {
+            let site = AllocSite::capture();
+            if let Some(arena) = self.arena_for_ptr(ptr) {
+                arena.set_site(ptr, site);
+            } else if let Some(heap) = self.heap_for_ptr(ptr) {
+                heap.set_site(ptr, site);
+            }
+        }
+        self.peak_used_bytes = self.peak_used_bytes.max(self.used_bytes());
+        ptr
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout, zeroed: bool) -> *mut u8 {
+        match slab_class(layout) {
+            Some(class) => self.alloc_object(class, zeroed),
+            None => self.alloc_block(layout, zeroed),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        match slab_class(layout) {
+            Some(class) => {
+                if let Some(slab) = self.slabs[class].dealloc(NonNull::new_unchecked(ptr)) {
+                    self.dealloc_block(slab.as_ptr());
+                }
+            }
+            None => self.dealloc_block(ptr),
+        }
+    }
+
+    unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (slab_class(layout), slab_class(new_layout)) {
+            (Some(class), Some(new_class)) if class == new_class => ptr,
+            (None, None) => self.realloc_block(ptr, layout, new_size),
+            _ => self.move_to(ptr, layout, new_layout),
+        }
+    }
+
+    /// Moves the block at `ptr` to a new block of `new_layout`, unless there is no memory left.
+    unsafe fn move_to(&mut self, ptr: *mut u8, layout: Layout, new_layout: Layout) -> *mut u8 {
+        let new_ptr = self.alloc(new_layout, false);
+        if !new_ptr.is_null() {
+            new_ptr.copy_from_nonoverlapping(ptr, layout.size().min(new_layout.size()));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+
+    unsafe fn alloc_object(&mut self, class: usize, zeroed: bool) -> *mut u8 {
+        let object = match self.slabs[class].alloc() {
+            Some(object) => object,
+            None => {
+                let Some(slab) = NonNull::new(self.alloc_block(slab_layout(), false)) else {
+                    return null_mut();
+                };
+                self.slabs[class].add_slab(slab);
+                self.slabs[class].alloc().unwrap()
+            }
+        };
+        if zeroed {
+            object.write_bytes(0, self.slabs[class].object_size());
+        }
+        object.as_ptr()
+    }
+
+    unsafe fn alloc_block(&mut self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let (size, align) = (layout.size(), layout.align());
+        if let Some(ptr) = self
+            .arenas()
+            .find_map(|arena| arena.alloc(size, align, zeroed))
+        {
+            return ptr;
+        }
+        self.heap
+            .as_mut()
+            .and_then(|heap| heap.alloc(size, align, zeroed))
+            .unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc_block(&mut self, ptr: *mut u8) {
+        if let Some(arena) = self.arena_for_ptr(ptr) {
+            arena.dealloc(ptr);
+        } else if let Some(heap) = self.heap_for_ptr(ptr) {
+            heap.dealloc(ptr);
+        } else {
+            #[cfg(feature = "heap-debug")]
+            foreign_pointer(ptr);
+        }
+    }
+
+    unsafe fn realloc_block(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let resized = if let Some(arena) = self.arena_for_ptr(ptr) {
+            arena.realloc(ptr, new_size, layout.align())
+        } else if let Some(heap) = self.heap_for_ptr(ptr) {
+            heap.realloc(ptr, new_size, layout.align())
+        } else {
+            #[cfg(feature = "heap-debug")]
+            foreign_pointer(ptr);
+            #[cfg(not(feature = "heap-debug"))]
+            return null_mut();
+        };
+        if let Some(new_ptr) = resized {
+            return new_ptr;
+        }
+
+        // The arena is full, so move the block to any other arena with enough space, or to the
+        // heap after growing it.
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        self.move_to(ptr, layout, new_layout)
+    }
+}
+
+/// Index of the slab cache serving `layout`, if it is small enough for one.
+fn slab_class(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align());
+    if !SLABS_ENABLED || size > MAX_SLAB_OBJECT {
+        return None;
+    }
+    let class = size
+        .max(MIN_SLAB_OBJECT)
+        .next_power_of_two()
+        .trailing_zeros();
+    Some((class - MIN_SLAB_OBJECT.trailing_zeros()) as usize)
+}
+
+const fn slab_caches() -> [RawSlabCache; SLAB_CLASSES] {
+    let mut caches = [const { RawSlabCache::new(MIN_SLAB_OBJECT, MIN_SLAB_OBJECT) }; SLAB_CLASSES];
+    let mut class = 1;
+    while class < SLAB_CLASSES {
+        let size = MIN_SLAB_OBJECT << class;
+        caches[class] = RawSlabCache::new(size, size);
+        class += 1;
+    }
+    caches
+}
+
+#[cfg(feature = "heap-debug")]
+#[cold]
+fn foreign_pointer(ptr: *mut u8) -> ! {
+    panic!("Heap corruption: freed {ptr:p}, which is not in any arena nor in the heap");
+}
+
 unsafe impl GlobalAlloc for SystemAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        match self.map.get().as_mut() {
-            Some(Some(map)) => map
-                .alloc(layout.size(), layout.align(), false)
-                .unwrap_or(null_mut()),
-            _ => null_mut(),
+        self.alloc_or_shrink(layout.size(), |inner| inner.alloc(layout, false))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.lock_irqsave().dealloc(ptr, layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc_or_shrink(layout.size(), |inner| inner.alloc(layout, true))
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.alloc_or_shrink(new_size, |inner| inner.realloc(ptr, layout, new_size))
+    }
+}
+
+unsafe impl Allocator for SystemAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { allocate_with(self, layout, false) }
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { allocate_with(self, layout, true) }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        deallocate_with(self, ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        resize_with(self, ptr, old_layout, new_layout, false)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        resize_with(self, ptr, old_layout, new_layout, true)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        resize_with(self, ptr, old_layout, new_layout, false)
+    }
+}
+
+/// `Allocator` on top of `GlobalAlloc`, which cannot hand out zero sized blocks.
+unsafe fn allocate_with(
+    allocator: &impl GlobalAlloc,
+    layout: Layout,
+    zeroed: bool,
+) -> Result<NonNull<[u8]>, AllocError> {
+    if layout.size() == 0 {
+        let dangling = NonNull::new(ptr::without_provenance_mut(layout.align())).unwrap();
+        return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+    }
+    let ptr = if zeroed {
+        allocator.alloc_zeroed(layout)
+    } else {
+        allocator.alloc(layout)
+    };
+    let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+    Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+}
+
+unsafe fn deallocate_with(allocator: &impl GlobalAlloc, ptr: NonNull<u8>, layout: Layout) {
+    if layout.size() != 0 {
+        allocator.dealloc(ptr.as_ptr(), layout);
+    }
+}
+
+/// Resizes in place through `realloc` when it can. `realloc` cannot change the alignment nor
+/// handle zero sized blocks, so those are moved instead.
+unsafe fn resize_with(
+    allocator: &impl GlobalAlloc,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+    zeroed: bool,
+) -> Result<NonNull<[u8]>, AllocError> {
+    let copy_size = old_layout.size().min(new_layout.size());
+    if old_layout.size() == 0 || new_layout.size() == 0 || old_layout.align() != new_layout.align()
+    {
+        let new_ptr = allocate_with(allocator, new_layout, zeroed)?;
+        new_ptr
+            .cast::<u8>()
+            .copy_from_nonoverlapping(ptr, copy_size);
+        deallocate_with(allocator, ptr, old_layout);
+        return Ok(new_ptr);
+    }
+
+    let new_ptr = allocator.realloc(ptr.as_ptr(), old_layout, new_layout.size());
+    let new_ptr = NonNull::new(new_ptr).ok_or(AllocError)?;
+    if zeroed {
+        new_ptr
+            .add(copy_size)
+            .write_bytes(0, new_layout.size() - copy_size);
+    }
+    Ok(NonNull::slice_from_raw_parts(new_ptr, new_layout.size()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alloc_sys::{SystemAllocator, BLOCK_BATCH, MIN_ARENA_SIZE};
+    use alloc::alloc::alloc;
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::mem::ManuallyDrop;
+    use core::ptr::NonNull;
+
+    /// An allocator with an arena of `arena_size` bytes. It must not be dropped, as the buffers of
+    /// its arenas are not owned by the global allocator.
+    unsafe fn test_allocator(arena_size: usize) -> ManuallyDrop<SystemAllocator> {
+        let allocator = ManuallyDrop::new(SystemAllocator::new());
+        add_test_arena(&allocator, arena_size);
+        allocator
+    }
+
+    unsafe fn add_test_arena(allocator: &SystemAllocator, size: usize) {
+        let layout = Layout::from_size_align(size, 4096).unwrap();
+        let ptr = NonNull::new(alloc(layout)).unwrap();
+        allocator.add_arena(ptr, size).unwrap();
+    }
+
+    #[test]
+    fn arena_fallthrough_test() {
+        unsafe {
+            let allocator = test_allocator(MIN_ARENA_SIZE);
+            add_test_arena(&allocator, MIN_ARENA_SIZE);
+            assert_eq!(allocator.arena_count(), 2);
+
+            let layout = Layout::from_size_align(MIN_ARENA_SIZE / 2, 16).unwrap();
+            let first = allocator.alloc(layout);
+            let second = allocator.alloc(layout);
+            assert!(!first.is_null() && !second.is_null());
+            let mut inner = allocator.inner.lock();
+            assert!(inner.arena_for_ptr(first).unwrap().contains(first));
+            assert!(!inner.arena_for_ptr(first).unwrap().contains(second));
+            drop(inner);
+            assert!(allocator.alloc(layout).is_null());
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        match self.map.get().as_mut() {
-            Some(Some(map)) => map.dealloc(ptr),
-            _ => {}
+    #[test]
+    fn allocator_stats_test() {
+        unsafe {
+            let allocator = test_allocator(MIN_ARENA_SIZE);
+            let empty = allocator.stats();
+            assert_eq!(empty.capacity, MIN_ARENA_SIZE);
+            assert_eq!((empty.used_bytes, empty.live_blocks), (0, 0));
+
+            #[cfg(feature = "alloc-tracking")]
+            let checkpoint = allocator.checkpoint();
+            // Too big for the slab caches
+            let layout = Layout::from_size_align(4000, 16).unwrap();
+            let first = allocator.alloc(layout);
+            let second = allocator.alloc(layout);
+            let stats = allocator.stats();
+            assert_eq!(stats.live_blocks, 2);
+            assert!(stats.used_bytes >= 8000);
+            assert_eq!(stats.used_bytes + stats.free_bytes, empty.free_bytes);
+            assert!(stats.largest_free_block < empty.largest_free_block);
+            #[cfg(feature = "alloc-tracking")]
+            {
+                let mut leaks = 0;
+                allocator.for_each_leak(checkpoint, |block| {
+                    assert!(block.site.sequence >= checkpoint);
+                    leaks += 1;
+                });
+                assert_eq!(leaks, 2);
+            }
+
+            allocator.dealloc(first, layout);
+            allocator.dealloc(second, layout);
+            let stats = allocator.stats();
+            assert_eq!((stats.used_bytes, stats.live_blocks), (0, 0));
+            assert_eq!(stats.largest_free_block, empty.largest_free_block);
+            assert!(stats.peak_used_bytes >= 8000);
         }
     }
 
-    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        match self.map.get().as_mut() {
-            Some(Some(map)) => map
-                .alloc(layout.size(), layout.align(), true)
-                .unwrap_or(null_mut()),
-            _ => null_mut(),
+    #[test]
+    fn for_each_block_unlocked_test() {
+        unsafe {
+            let allocator = test_allocator(4 * MIN_ARENA_SIZE);
+            let layout = Layout::from_size_align(3000, 16).unwrap();
+            for _ in 0..BLOCK_BATCH + 8 {
+                assert!(!allocator.alloc(layout).is_null());
+            }
+
+            let mut blocks = 0;
+            let mut last_start = None;
+            allocator.for_each_block_unlocked(|_, _, block| {
+                // The allocator is not locked, so this would hang before
+                let ptr = allocator.alloc(layout);
+                allocator.dealloc(ptr, layout);
+                assert!(last_start < Some(block.start));
+                last_start = Some(block.start);
+                blocks += 1;
+            });
+            // Every block that was allocated and the free block after them
+            assert_eq!(blocks, BLOCK_BATCH + 9);
         }
     }
 
-    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        match self.map.get().as_mut() {
-            Some(Some(map)) => map
-                .realloc(ptr, new_size, layout.align())
-                .unwrap_or(null_mut()),
-            _ => null_mut(),
+    #[cfg(not(any(feature = "heap-debug", feature = "alloc-tracking")))]
+    #[test]
+    fn slab_classes_test() {
+        unsafe {
+            let allocator = test_allocator(4 * MIN_ARENA_SIZE);
+
+            let small = Layout::from_size_align(24, 8).unwrap();
+            let objects = [allocator.alloc(small), allocator.alloc_zeroed(small)];
+            assert_eq!(objects[1].cast::<[u8; 24]>().read(), [0; 24]);
+            let aligned = allocator.alloc(Layout::from_size_align(8, 64).unwrap());
+            assert!(!aligned.is_null() && (aligned as usize).is_multiple_of(64));
+            let stats = allocator.slab_stats();
+            assert_eq!((stats[1].object_size, stats[1].objects_in_use), (32, 2));
+            assert_eq!(stats[2].objects_in_use, 1);
+            // Every slab is a single block of the arena
+            assert_eq!(allocator.stats().live_blocks, 2);
+
+            // Growing within the size class keeps the object, growing past it moves it
+            objects[0].write_bytes(7, 24);
+            assert_eq!(allocator.realloc(objects[0], small, 32), objects[0]);
+            let moved = allocator.realloc(objects[0], small, 3000);
+            assert_eq!(moved.cast::<[u8; 24]>().read(), [7; 24]);
+            assert_eq!(allocator.slab_stats()[1].objects_in_use, 1);
+
+            allocator.dealloc(moved, Layout::from_size_align(3000, 8).unwrap());
+            allocator.dealloc(objects[1], small);
+            allocator.dealloc(aligned, Layout::from_size_align(8, 64).unwrap());
+            let stats = allocator.slab_stats();
+            assert!(stats.iter().all(|stats| stats.objects_in_use == 0));
+            // The last empty slab of each cache is kept
+            assert_eq!(allocator.stats().live_blocks, 2);
         }
     }
-}
 
-unsafe impl Sync for SystemAllocator {}
+    #[cfg(not(any(feature = "heap-debug", feature = "alloc-tracking")))]
+    #[test]
+    fn trim_on_oom_test() {
+        unsafe {
+            let allocator = test_allocator(MIN_ARENA_SIZE);
+
+            let small = Layout::from_size_align(16, 16).unwrap();
+            allocator.dealloc(allocator.alloc(small), small);
+            assert_eq!(allocator.slab_stats()[0].slabs, 1);
+
+            // Only fits once the empty slab is given back
+            let size = allocator.stats().largest_free_block + 1;
+            let ptr = allocator.alloc(Layout::from_size_align(size, 16).unwrap());
+            assert!(!ptr.is_null());
+            assert_eq!(allocator.slab_stats()[0].slabs, 0);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::sync::SpinLock;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub const MAX_SHRINKERS: usize = 8;
+
+/// Frees memory that can be rebuilt later, such as a cache, when an allocation fails. Gets the
+/// size of the failed allocation, and returns how many bytes it freed.
+pub type Shrinker = fn(usize) -> usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShrinkerId(usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShrinkerError {
+    TooManyShrinkers,
+}
+
+static SHRINKERS: SpinLock<[Option<Shrinker>; MAX_SHRINKERS]> =
+    SpinLock::new([None; MAX_SHRINKERS]);
+/// Set while the shrinkers run, so that a shrinker that fails to allocate does not run them again.
+static SHRINKING: AtomicBool = AtomicBool::new(false);
+
+/// Registers `shrinker` to run whenever the system allocator runs out of memory.
+pub fn register_shrinker(shrinker: Shrinker) -> Result<ShrinkerId, ShrinkerError> {
+    let mut shrinkers = SHRINKERS.lock_irqsave();
+    let index = shrinkers
+        .iter()
+        .position(Option::is_none)
+        .ok_or(ShrinkerError::TooManyShrinkers)?;
+    shrinkers[index] = Some(shrinker);
+    Ok(ShrinkerId(index))
+}
+
+pub fn unregister_shrinker(id: ShrinkerId) {
+    SHRINKERS.lock_irqsave()[id.0] = None;
+}
+
+/// Runs every shrinker to make room for an allocation of `needed` bytes, and returns how many
+/// bytes they freed in total.
+///
+/// The allocator must not be locked, as the shrinkers free memory.
+pub fn run_shrinkers(needed: usize) -> usize {
+    if SHRINKING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    // Copy them, so that shrinkers can register or unregister others
+    let shrinkers = *SHRINKERS.lock_irqsave();
+    let freed = shrinkers
+        .iter()
+        .flatten()
+        .map(|shrink| shrink(needed))
+        .sum();
+    SHRINKING.store(false, Ordering::Release);
+    freed
+}
+
+/// Called when an allocation fails even after running the shrinkers, and the code that asked for
+/// it cannot handle the failure.
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    use crate::alloc_sys::ALLOCATOR;
+    use crate::logger::logln;
+
+    logln!(
+        "Out of memory: cannot allocate {} bytes aligned to {}.",
+        layout.size(),
+        layout.align()
+    );
+    logln!("Allocator: {}", ALLOCATOR.stats());
+    panic!("Out of memory.");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alloc_sys::oom::{register_shrinker, run_shrinkers, unregister_shrinker};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Other tests run out of memory on purpose, so only count the calls for this size.
+    const NEEDED: usize = 123_456_789;
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_calls(needed: usize) -> usize {
+        if needed != NEEDED {
+            return 0;
+        }
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        // Running the shrinkers again from a shrinker does nothing
+        assert_eq!(run_shrinkers(NEEDED), 0);
+        100
+    }
+
+    #[test]
+    fn shrinker_test() {
+        let id = register_shrinker(count_calls).unwrap();
+        // Other tests may be running the shrinkers at the same time
+        while run_shrinkers(NEEDED) == 0 {}
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+        unregister_shrinker(id);
+        run_shrinkers(NEEDED);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+    }
+}
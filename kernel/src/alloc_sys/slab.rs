@@ -0,0 +1,394 @@
+use crate::sync::SpinLock;
+use alloc::alloc::Global;
+use core::alloc::{AllocError, Allocator, Layout};
+use core::fmt::{self, Display, Formatter};
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, null_mut, NonNull};
+
+/// Every slab is this big, and aligned to its size, so that the slab of an object is found by
+/// rounding its address down.
+pub const SLAB_SIZE: usize = 16 * 1024;
+
+/// Sits at the start of every slab, before its objects.
+#[repr(C)]
+struct Slab {
+    prev: *mut Slab,
+    next: *mut Slab,
+    /// Free objects of this slab, linked through their first bytes.
+    free: *mut FreeObject,
+    in_use: usize,
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// A doubly linked list of slabs.
+struct SlabList {
+    head: *mut Slab,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SlabStats {
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+/// Hands out objects of a single size, carved out of slabs that the caller provides.
+///
+/// Slabs with free objects are kept in a list, so allocating and freeing are O(1). Full slabs are
+/// kept in another one, and at most one empty slab is kept around: the others are handed back as
+/// soon as their last object is freed.
+pub struct RawSlabCache {
+    object_size: usize,
+    /// Offset of the first object in a slab, past the header.
+    first_object: usize,
+    partial: SlabList,
+    full: SlabList,
+    empty: *mut Slab,
+    stats: SlabStats,
+}
+
+// The cache owns its slabs, the raw pointers never leave it.
+unsafe impl Send for RawSlabCache {}
+
+impl RawSlabCache {
+    /// A cache of objects of `size` bytes aligned to `align`, which must be a power of two.
+    pub const fn new(size: usize, align: usize) -> Self {
+        assert!(align.is_power_of_two() && align <= SLAB_SIZE);
+        let align = if align > align_of::<FreeObject>() {
+            align
+        } else {
+            align_of::<FreeObject>()
+        };
+        let size = if size > size_of::<FreeObject>() {
+            size
+        } else {
+            size_of::<FreeObject>()
+        };
+        let object_size = size.next_multiple_of(align);
+        let first_object = size_of::<Slab>().next_multiple_of(align);
+        assert!(
+            first_object + object_size <= SLAB_SIZE,
+            "Slab objects too big"
+        );
+        Self {
+            object_size,
+            first_object,
+            partial: SlabList { head: null_mut() },
+            full: SlabList { head: null_mut() },
+            empty: null_mut(),
+            stats: SlabStats {
+                object_size,
+                objects_per_slab: (SLAB_SIZE - first_object) / object_size,
+                slabs: 0,
+                objects_in_use: 0,
+                allocations: 0,
+                frees: 0,
+            },
+        }
+    }
+
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.stats
+    }
+
+    /// Takes a free object, or returns `None` if every slab is full. The cache can then be given
+    /// a new slab with [`RawSlabCache::add_slab`].
+    pub fn alloc(&mut self) -> Option<NonNull<u8>> {
+        if self.partial.head.is_null() {
+            if self.empty.is_null() {
+                return None;
+            }
+            unsafe { self.partial.push(self.empty) };
+            self.empty = null_mut();
+        }
+        unsafe {
+            let slab = self.partial.head;
+            let object = (*slab).free;
+            (*slab).free = (*object).next;
+            (*slab).in_use += 1;
+            if (*slab).free.is_null() {
+                self.partial.remove(slab);
+                self.full.push(slab);
+            }
+            self.stats.objects_in_use += 1;
+            self.stats.allocations += 1;
+            NonNull::new(object.cast())
+        }
+    }
+
+    /// Gives back the object at `ptr`. Returns the slab it was in if it is now empty and the cache
+    /// does not need it anymore.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this cache, and must not be used anymore.
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>) -> Option<NonNull<u8>> {
+        let slab = slab_of(ptr.as_ptr());
+        let object = ptr.as_ptr().cast::<FreeObject>();
+        if (*slab).free.is_null() {
+            self.full.remove(slab);
+            self.partial.push(slab);
+        }
+        (*object).next = (*slab).free;
+        (*slab).free = object;
+        (*slab).in_use -= 1;
+        self.stats.objects_in_use -= 1;
+        self.stats.frees += 1;
+        if (*slab).in_use != 0 {
+            return None;
+        }
+
+        self.partial.remove(slab);
+        if self.empty.is_null() {
+            self.empty = slab;
+            return None;
+        }
+        self.stats.slabs -= 1;
+        NonNull::new(slab.cast())
+    }
+
+    /// Carves the [`SLAB_SIZE`] bytes at `ptr` into objects.
+    ///
+    /// # Safety
+    /// `ptr` must be aligned to [`SLAB_SIZE`], and the slab must be used by nothing else until the
+    /// cache hands it back.
+    pub unsafe fn add_slab(&mut self, ptr: NonNull<u8>) {
+        debug_assert!(ptr.addr().get().is_multiple_of(SLAB_SIZE));
+        let slab = ptr.as_ptr().cast::<Slab>();
+        let mut free = null_mut();
+        let mut offset = self.first_object + self.stats.objects_per_slab * self.object_size;
+        while offset > self.first_object {
+            offset -= self.object_size;
+            let object = ptr.as_ptr().add(offset).cast::<FreeObject>();
+            (*object).next = free;
+            free = object;
+        }
+        slab.write(Slab {
+            prev: null_mut(),
+            next: null_mut(),
+            free,
+            in_use: 0,
+        });
+        self.partial.push(slab);
+        self.stats.slabs += 1;
+    }
+
+    /// Hands back the empty slab kept for later allocations, if there is one.
+    pub fn take_empty_slab(&mut self) -> Option<NonNull<u8>> {
+        let slab = NonNull::new(self.empty)?;
+        self.empty = null_mut();
+        self.stats.slabs -= 1;
+        Some(slab.cast())
+    }
+
+    /// Hands back one slab, which may still have objects in use. Only meant to drop the whole
+    /// cache.
+    fn take_any_slab(&mut self) -> Option<NonNull<u8>> {
+        if let Some(slab) = self.take_empty_slab() {
+            return Some(slab);
+        }
+        let list = if self.partial.head.is_null() {
+            &mut self.full
+        } else {
+            &mut self.partial
+        };
+        let slab = NonNull::new(list.head)?;
+        unsafe { list.remove(slab.as_ptr()) };
+        self.stats.slabs -= 1;
+        Some(slab.cast())
+    }
+}
+
+impl SlabList {
+    unsafe fn push(&mut self, slab: *mut Slab) {
+        (*slab).prev = null_mut();
+        (*slab).next = self.head;
+        if !self.head.is_null() {
+            (*self.head).prev = slab;
+        }
+        self.head = slab;
+    }
+
+    unsafe fn remove(&mut self, slab: *mut Slab) {
+        let Slab { prev, next, .. } = slab.read();
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        if prev.is_null() {
+            self.head = next;
+        } else {
+            (*prev).next = next;
+        }
+    }
+}
+
+fn slab_of(ptr: *mut u8) -> *mut Slab {
+    ptr.map_addr(|addr| addr & !(SLAB_SIZE - 1)).cast()
+}
+
+impl Display for SlabStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} byte objects: {} in use in {} slabs of {} ({} allocations, {} frees)",
+            self.object_size,
+            self.objects_in_use,
+            self.slabs,
+            self.objects_per_slab,
+            self.allocations,
+            self.frees
+        )
+    }
+}
+
+/// A cache of `T`s, taking its slabs from `allocator`.
+///
+/// Objects are handed out through the [`Allocator`] trait, for example with `Box::new_in(value,
+/// &cache)`, or uninitialized with [`SlabCache::alloc`].
+pub struct SlabCache<T, A: Allocator = Global> {
+    raw: SpinLock<RawSlabCache>,
+    allocator: A,
+    _marker: PhantomData<T>,
+}
+
+impl<T, A: Allocator> SlabCache<T, A> {
+    pub const fn new(allocator: A) -> Self {
+        Self {
+            raw: SpinLock::new(RawSlabCache::new(size_of::<T>(), align_of::<T>())),
+            allocator,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.raw.lock_irqsave().stats()
+    }
+
+    /// Takes an uninitialized object, getting a new slab from the allocator if they are all full.
+    pub fn alloc(&self) -> Option<NonNull<T>> {
+        let mut raw = self.raw.lock_irqsave();
+        if let Some(object) = raw.alloc() {
+            return Some(object.cast());
+        }
+        let slab = self.allocator.allocate(slab_layout()).ok()?;
+        unsafe { raw.add_slab(slab.cast()) };
+        raw.alloc().map(NonNull::cast)
+    }
+
+    /// # Safety
+    /// `ptr` must have been allocated by this cache, and the object must not be used anymore. It
+    /// is not dropped.
+    pub unsafe fn free(&self, ptr: NonNull<T>) {
+        let slab = self.raw.lock_irqsave().dealloc(ptr.cast());
+        if let Some(slab) = slab {
+            self.allocator.deallocate(slab, slab_layout());
+        }
+    }
+
+    /// Gives the empty slab kept by the cache back to the allocator. Returns how many bytes were
+    /// released.
+    pub fn shrink(&self) -> usize {
+        let slab = self.raw.lock_irqsave().take_empty_slab();
+        match slab {
+            Some(slab) => {
+                unsafe { self.allocator.deallocate(slab, slab_layout()) };
+                SLAB_SIZE
+            }
+            None => 0,
+        }
+    }
+
+    fn fits(&self, layout: Layout) -> bool {
+        layout.size() <= size_of::<T>() && layout.align() <= align_of::<T>()
+    }
+}
+
+impl<T, A: Allocator> Drop for SlabCache<T, A> {
+    fn drop(&mut self) {
+        let mut raw = self.raw.lock();
+        while let Some(slab) = raw.take_any_slab() {
+            unsafe { self.allocator.deallocate(slab, slab_layout()) };
+        }
+    }
+}
+
+/// Only layouts that fit a `T` can be allocated.
+unsafe impl<T, A: Allocator> Allocator for SlabCache<T, A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.fits(layout) {
+            return Err(AllocError);
+        }
+        if layout.size() == 0 {
+            let dangling = NonNull::new(ptr::without_provenance_mut(layout.align())).unwrap();
+            return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+        }
+        let object = self.alloc().ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(object.cast(), layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            self.free(ptr.cast());
+        }
+    }
+}
+
+pub const fn slab_layout() -> Layout {
+    unsafe { Layout::from_size_align_unchecked(SLAB_SIZE, SLAB_SIZE) }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alloc_sys::slab::{SlabCache, SLAB_SIZE};
+    use alloc::alloc::Global;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn slab_cache_test() {
+        let cache = SlabCache::<[u64; 3]>::new(Global);
+        let per_slab = cache.stats().objects_per_slab;
+        assert_eq!(cache.stats().object_size, 24);
+        assert_eq!(per_slab, (SLAB_SIZE - 32) / 24);
+
+        let objects = (0..per_slab + 1)
+            .map(|i| Box::new_in([i as u64; 3], &cache))
+            .collect::<Vec<_>>();
+        let stats = cache.stats();
+        assert_eq!((stats.slabs, stats.objects_in_use), (2, per_slab + 1));
+        for (i, object) in objects.iter().enumerate() {
+            assert_eq!(**object, [i as u64; 3]);
+        }
+
+        // Emptying both slabs keeps one of them for later
+        drop(objects);
+        let stats = cache.stats();
+        assert_eq!((stats.slabs, stats.objects_in_use), (1, 0));
+        assert_eq!(
+            (stats.allocations, stats.frees),
+            (per_slab as u64 + 1, per_slab as u64 + 1)
+        );
+        let object = cache.alloc().unwrap();
+        assert_eq!(cache.stats().slabs, 1);
+        unsafe { cache.free(object) };
+        assert_eq!(cache.shrink(), SLAB_SIZE);
+        assert_eq!(cache.stats().slabs, 0);
+
+        // Only objects that fit are handed out
+        assert!(Box::try_new_in([0u64; 4], &cache).is_err());
+    }
+}
@@ -0,0 +1,41 @@
+use core::fmt::{self, Display, Formatter};
+
+/// A snapshot of the usage of an allocator. Sizes include the header of every block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    pub capacity: usize,
+    pub used_bytes: usize,
+    pub free_bytes: usize,
+    pub live_blocks: usize,
+    /// The biggest allocation that can succeed without growing the heap.
+    pub largest_free_block: usize,
+    pub peak_used_bytes: usize,
+}
+
+impl AllocatorStats {
+    pub(super) fn merge(self, other: Self) -> Self {
+        Self {
+            capacity: self.capacity + other.capacity,
+            used_bytes: self.used_bytes + other.used_bytes,
+            free_bytes: self.free_bytes + other.free_bytes,
+            live_blocks: self.live_blocks + other.live_blocks,
+            largest_free_block: self.largest_free_block.max(other.largest_free_block),
+            peak_used_bytes: self.peak_used_bytes + other.peak_used_bytes,
+        }
+    }
+}
+
+impl Display for AllocatorStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes used by {} blocks ({} free, largest free block {}, peak {})",
+            self.used_bytes,
+            self.capacity,
+            self.live_blocks,
+            self.free_bytes,
+            self.largest_free_block,
+            self.peak_used_bytes
+        )
+    }
+}
@@ -0,0 +1,90 @@
+use core::fmt::{self, Display, Formatter};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of return addresses recorded for each allocation.
+pub const TRACKED_FRAMES: usize = 4;
+/// Frames further apart than this are not part of the same stack.
+const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(1);
+
+/// Where and when a block was allocated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocSite {
+    /// Allocations are numbered in order, starting at 1. 0 means the site is unknown.
+    pub sequence: u64,
+    /// Return addresses of the callers of the allocator, innermost first, and 0 past the end of
+    /// the stack.
+    pub callers: [usize; TRACKED_FRAMES],
+}
+
+impl AllocSite {
+    pub const EMPTY: Self = Self {
+        sequence: 0,
+        callers: [0; TRACKED_FRAMES],
+    };
+
+    /// Numbers a new allocation and walks the frame pointers to find its callers. The kernel is
+    /// built with frame pointers when this feature is on.
+    #[inline(never)]
+    pub fn capture() -> Self {
+        Self {
+            sequence: NEXT_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+            callers: callers(),
+        }
+    }
+}
+
+/// Sequence number the next allocation will get. Blocks allocated from now on can be listed by
+/// passing it to [`crate::alloc_sys::SystemAllocator::leak_report`].
+pub fn next_sequence() -> u64 {
+    NEXT_SEQUENCE.load(Ordering::Relaxed)
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+fn callers() -> [usize; TRACKED_FRAMES] {
+    let mut callers = [0; TRACKED_FRAMES];
+    let mut frame: usize;
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) frame, options(nomem, nostack)) };
+    // The first frame is the allocator itself
+    let mut skip = 1;
+    let mut index = 0;
+    while index < TRACKED_FRAMES && frame != 0 && frame.is_multiple_of(size_of::<usize>()) {
+        let (next, return_address) = unsafe {
+            let frame = frame as *const usize;
+            (*frame, *frame.add(1))
+        };
+        if return_address == 0 {
+            break;
+        }
+        if skip > 0 {
+            skip -= 1;
+        } else {
+            callers[index] = return_address;
+            index += 1;
+        }
+        if next <= frame || next - frame > MAX_FRAME_SIZE {
+            break;
+        }
+        frame = next;
+    }
+    callers
+}
+
+/// Host tests are not built with frame pointers, so there is nothing to walk.
+#[cfg(test)]
+fn callers() -> [usize; TRACKED_FRAMES] {
+    [0; TRACKED_FRAMES]
+}
+
+impl Display for AllocSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.sequence)?;
+        for (index, caller) in self.callers.iter().take_while(|&&c| c != 0).enumerate() {
+            let separator = if index == 0 { " from" } else { " <-" };
+            write!(f, "{separator} {caller:#x}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+/// Size of the stack the bootloader allocates for the kernel.
+///
+/// The bootloader places a guard page right below it, so an overflow page faults on that page
+/// and, as the page fault handler cannot push its frame either, turns into a double fault.
+pub const KERNEL_STACK_SIZE: u64 = 80 * 1024;
+pub const KERNEL_STACK_GUARD_SIZE: u64 = PAGE_SIZE;
+
+const PAGE_SIZE: u64 = 4096;
+const IST_STACK_SIZE: usize = PAGE_SIZE as usize * 5;
+
+#[repr(C, align(16))]
+struct InterruptStack([u8; IST_STACK_SIZE]);
+
+static mut DOUBLE_FAULT_STACK: InterruptStack = InterruptStack([0; IST_STACK_SIZE]);
+static mut NMI_STACK: InterruptStack = InterruptStack([0; IST_STACK_SIZE]);
+static mut MACHINE_CHECK_STACK: InterruptStack = InterruptStack([0; IST_STACK_SIZE]);
+
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+static mut GDT: GlobalDescriptorTable = GlobalDescriptorTable::new();
+static mut SELECTORS: Option<Selectors> = None;
+static mut KERNEL_STACK_BOTTOM: u64 = 0;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+/// Loads the GDT and the TSS, and reloads the segment registers.
+///
+/// `kernel_stack_bottom` is the lowest address of the stack set up by the bootloader, as given in
+/// the boot info. The guard page lies right below it.
+pub fn initialize_gdt(kernel_stack_bottom: u64) {
+    unsafe {
+        if SELECTORS.is_some() {
+            return;
+        }
+
+        TSS.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack_top(&DOUBLE_FAULT_STACK);
+        TSS.interrupt_stack_table[NMI_IST_INDEX as usize] = stack_top(&NMI_STACK);
+        TSS.interrupt_stack_table[MACHINE_CHECK_IST_INDEX as usize] =
+            stack_top(&MACHINE_CHECK_STACK);
+
+        // The user data segment goes before the user code segment, as `sysret` expects them in
+        // that order.
+        let selectors = Selectors {
+            kernel_code: GDT.append(Descriptor::kernel_code_segment()),
+            kernel_data: GDT.append(Descriptor::kernel_data_segment()),
+            user_data: GDT.append(Descriptor::user_data_segment()),
+            user_code: GDT.append(Descriptor::user_code_segment()),
+            tss: GDT.append(Descriptor::tss_segment(&TSS)),
+        };
+
+        GDT.load();
+        CS::set_reg(selectors.kernel_code);
+        DS::set_reg(selectors.kernel_data);
+        ES::set_reg(selectors.kernel_data);
+        SS::set_reg(selectors.kernel_data);
+        load_tss(selectors.tss);
+
+        SELECTORS = Some(selectors);
+        KERNEL_STACK_BOTTOM = kernel_stack_bottom;
+    }
+}
+
+pub fn selectors() -> Selectors {
+    unsafe { SELECTORS.expect("The GDT has not been initialized yet.") }
+}
+
+/// Returns whether `addr` lies in the guard page below the kernel stack.
+pub fn is_kernel_stack_guard(addr: u64) -> bool {
+    let bottom = unsafe { KERNEL_STACK_BOTTOM };
+    bottom != 0 && addr < bottom && addr >= bottom - KERNEL_STACK_GUARD_SIZE
+}
+
+fn stack_top(stack: &'static InterruptStack) -> VirtAddr {
+    VirtAddr::from_ptr(stack) + IST_STACK_SIZE as u64
+}
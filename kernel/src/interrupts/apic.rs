@@ -0,0 +1,159 @@
+use crate::acpi::InterruptOverride;
+use core::ptr;
+use x86_64::registers::model_specific::Msr;
+
+const IA32_APIC_BASE_MSR: u32 = 0x1b;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+const LAPIC_ID: usize = 0x20;
+const LAPIC_TASK_PRIORITY: usize = 0x80;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SPURIOUS_VECTOR: usize = 0xf0;
+const LAPIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+const IOAPIC_REGISTER_SELECT: usize = 0x00;
+const IOAPIC_WINDOW: usize = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u64 = 1 << 15;
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+#[derive(Debug, Copy, Clone)]
+pub struct LocalApic {
+    base: *mut u32,
+}
+
+impl LocalApic {
+    /// # Safety
+    /// `base` must be the virtual address the local APIC registers are mapped at.
+    pub unsafe fn new(base: u64) -> Self {
+        Self {
+            base: base as *mut u32,
+        }
+    }
+
+    pub fn enable(&mut self, spurious_vector: u8) {
+        unsafe {
+            let mut apic_base = Msr::new(IA32_APIC_BASE_MSR);
+            let value = apic_base.read();
+            apic_base.write(value | APIC_GLOBAL_ENABLE);
+
+            self.write(LAPIC_TASK_PRIORITY, 0);
+            self.write(
+                LAPIC_SPURIOUS_VECTOR,
+                LAPIC_SOFTWARE_ENABLE | spurious_vector as u32,
+            );
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        unsafe { (self.read(LAPIC_ID) >> 24) as u8 }
+    }
+
+    pub fn end_of_interrupt(&mut self) {
+        unsafe { self.write(LAPIC_EOI, 0) };
+    }
+
+    pub unsafe fn read(&self, register: usize) -> u32 {
+        ptr::read_volatile(self.base.byte_add(register))
+    }
+
+    pub unsafe fn write(&mut self, register: usize, value: u32) {
+        ptr::write_volatile(self.base.byte_add(register), value)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct IoApic {
+    base: *mut u32,
+    gsi_base: u32,
+}
+
+impl IoApic {
+    /// # Safety
+    /// `base` must be the virtual address the IO APIC registers are mapped at.
+    pub unsafe fn new(base: u64, gsi_base: u32) -> Self {
+        Self {
+            base: base as *mut u32,
+            gsi_base,
+        }
+    }
+
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    pub fn redirection_entries(&self) -> u32 {
+        unsafe { ((self.read(IOAPIC_VERSION) >> 16) & 0xff) + 1 }
+    }
+
+    pub fn mask_all(&mut self) {
+        for entry in 0..self.redirection_entries() {
+            unsafe {
+                let low = self.read(IOAPIC_REDIRECTION_TABLE + entry * 2);
+                self.write(
+                    IOAPIC_REDIRECTION_TABLE + entry * 2,
+                    low | REDIRECTION_MASKED as u32,
+                );
+            }
+        }
+    }
+
+    /// Routes the global system interrupt `gsi` to `vector` on the local APIC `apic_id`. The line
+    /// stays masked until [`IoApic::set_masked`] is called.
+    pub fn route(
+        &mut self,
+        gsi: u32,
+        vector: u8,
+        apic_id: u8,
+        interrupt_override: Option<InterruptOverride>,
+    ) {
+        let mut entry = vector as u64 | REDIRECTION_MASKED | ((apic_id as u64) << 56);
+        if let Some(interrupt_override) = interrupt_override {
+            if interrupt_override.is_active_low() {
+                entry |= REDIRECTION_ACTIVE_LOW;
+            }
+            if interrupt_override.is_level_triggered() {
+                entry |= REDIRECTION_LEVEL_TRIGGERED;
+            }
+        }
+        self.write_entry(gsi, entry);
+    }
+
+    pub fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let entry = self.read_entry(gsi);
+        self.write_entry(
+            gsi,
+            if masked {
+                entry | REDIRECTION_MASKED
+            } else {
+                entry & !REDIRECTION_MASKED
+            },
+        );
+    }
+
+    fn read_entry(&self, gsi: u32) -> u64 {
+        let register = IOAPIC_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        unsafe { self.read(register) as u64 | ((self.read(register + 1) as u64) << 32) }
+    }
+
+    fn write_entry(&mut self, gsi: u32, entry: u64) {
+        let register = IOAPIC_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2;
+        unsafe {
+            self.write(register, entry as u32);
+            self.write(register + 1, (entry >> 32) as u32);
+        }
+    }
+
+    unsafe fn read(&self, register: u32) -> u32 {
+        ptr::write_volatile(self.base.byte_add(IOAPIC_REGISTER_SELECT), register);
+        ptr::read_volatile(self.base.byte_add(IOAPIC_WINDOW))
+    }
+
+    unsafe fn write(&mut self, register: u32, value: u32) {
+        ptr::write_volatile(self.base.byte_add(IOAPIC_REGISTER_SELECT), register);
+        ptr::write_volatile(self.base.byte_add(IOAPIC_WINDOW), value);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::acpi::{self, MAX_IO_APICS};
+use crate::interrupts::apic::{IoApic, LocalApic};
+use crate::interrupts::{pic, INTERRUPT_DEPTH};
+use crate::logger::logln;
+use crate::memory::{self, PAGE_SIZE};
+use core::sync::atomic::Ordering;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::PhysAddr;
+
+/// Legacy IRQs 0-15 are delivered on the vectors right after the CPU exceptions.
+pub const IRQ_BASE_VECTOR: u8 = 32;
+pub const IRQ_COUNT: usize = 16;
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+pub type IrqHandler = fn();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IrqError {
+    InvalidIrq(u8),
+    AlreadyRegistered(u8),
+    NotInitialized,
+}
+
+// There is a single instance of this, so the size of the APIC variant does not matter.
+#[allow(clippy::large_enum_variant)]
+enum InterruptController {
+    Pic,
+    Apic {
+        local_apic: LocalApic,
+        io_apics: [Option<IoApic>; MAX_IO_APICS],
+        madt: acpi::MadtInfo,
+    },
+}
+
+static mut CONTROLLER: Option<InterruptController> = None;
+static mut IRQ_HANDLERS: [Option<IrqHandler>; IRQ_COUNT] = [None; IRQ_COUNT];
+
+const IRQ_STUBS: [HandlerFunc; IRQ_COUNT] = [
+    irq_stub::<0>,
+    irq_stub::<1>,
+    irq_stub::<2>,
+    irq_stub::<3>,
+    irq_stub::<4>,
+    irq_stub::<5>,
+    irq_stub::<6>,
+    irq_stub::<7>,
+    irq_stub::<8>,
+    irq_stub::<9>,
+    irq_stub::<10>,
+    irq_stub::<11>,
+    irq_stub::<12>,
+    irq_stub::<13>,
+    irq_stub::<14>,
+    irq_stub::<15>,
+];
+
+pub(super) fn install_handlers(idt: &mut InterruptDescriptorTable) {
+    for (irq, stub) in IRQ_STUBS.iter().enumerate() {
+        idt[IRQ_BASE_VECTOR + irq as u8].set_handler_fn(*stub);
+    }
+    idt[SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
+}
+
+/// Sets up the interrupt controllers, with every IRQ line masked.
+///
+/// The legacy PICs are always remapped, so that spurious interrupts from them do not land on the
+/// exception vectors. If ACPI reports an IO APIC, the PICs are then disabled and the IRQs are
+/// routed through the IO APIC to the local APIC of this CPU.
+pub fn initialize_irqs(rsdp_addr: Option<u64>) {
+    pic::remap(IRQ_BASE_VECTOR);
+
+    let physical_memory_offset = memory::physical_memory_offset();
+    let madt = rsdp_addr.map(|rsdp_addr| acpi::find_madt(rsdp_addr, physical_memory_offset));
+    let controller = match madt {
+        Some(Ok(madt)) if madt.io_apics.iter().any(Option::is_some) => {
+            pic::disable();
+
+            let mut local_apic = unsafe { LocalApic::new(map_registers(madt.local_apic_address)) };
+            local_apic.enable(SPURIOUS_VECTOR);
+
+            let mut io_apics = [None; MAX_IO_APICS];
+            for (slot, info) in io_apics.iter_mut().zip(madt.io_apics.iter()) {
+                if let Some(info) = info {
+                    let mut io_apic =
+                        unsafe { IoApic::new(map_registers(info.address), info.gsi_base) };
+                    io_apic.mask_all();
+                    *slot = Some(io_apic);
+                }
+            }
+
+            for irq in 0..IRQ_COUNT as u8 {
+                let (gsi, interrupt_override) = madt.isa_irq_to_gsi(irq);
+                if let Some(io_apic) = io_apic_for_gsi(&mut io_apics, gsi) {
+                    io_apic.route(
+                        gsi,
+                        IRQ_BASE_VECTOR + irq,
+                        local_apic.id(),
+                        interrupt_override,
+                    );
+                }
+            }
+
+            logln!(
+                "Using the local APIC at {:#x} and {} IO APIC(s).",
+                madt.local_apic_address,
+                io_apics.iter().flatten().count()
+            );
+            InterruptController::Apic {
+                local_apic,
+                io_apics,
+                madt,
+            }
+        }
+        Some(Err(error)) => {
+            logln!("Cannot read the MADT ({error:?}), using the legacy PIC.");
+            InterruptController::Pic
+        }
+        _ => {
+            logln!("ACPI does not report an IO APIC, using the legacy PIC.");
+            InterruptController::Pic
+        }
+    };
+
+    unsafe { CONTROLLER = Some(controller) };
+}
+
+/// Registers `handler` to run whenever `irq` fires, and unmasks its line.
+///
+/// The handler runs with interrupts disabled, and the end of interrupt is sent once it returns.
+pub fn register_irq_handler(irq: u8, handler: IrqHandler) -> Result<(), IrqError> {
+    if irq as usize >= IRQ_COUNT {
+        return Err(IrqError::InvalidIrq(irq));
+    }
+    without_interrupts(|| unsafe {
+        if CONTROLLER.is_none() {
+            return Err(IrqError::NotInitialized);
+        }
+        if IRQ_HANDLERS[irq as usize].is_some() {
+            return Err(IrqError::AlreadyRegistered(irq));
+        }
+        IRQ_HANDLERS[irq as usize] = Some(handler);
+        set_masked(irq, false);
+        Ok(())
+    })
+}
+
+pub fn unregister_irq_handler(irq: u8) {
+    if irq as usize >= IRQ_COUNT {
+        return;
+    }
+    without_interrupts(|| unsafe {
+        set_masked(irq, true);
+        IRQ_HANDLERS[irq as usize] = None;
+    })
+}
+
+unsafe fn set_masked(irq: u8, masked: bool) {
+    match CONTROLLER.as_mut() {
+        Some(InterruptController::Pic) => pic::set_masked(irq, masked),
+        Some(InterruptController::Apic { io_apics, madt, .. }) => {
+            let (gsi, _) = madt.isa_irq_to_gsi(irq);
+            if let Some(io_apic) = io_apic_for_gsi(io_apics, gsi) {
+                io_apic.set_masked(gsi, masked);
+            }
+        }
+        None => {}
+    }
+}
+
+fn io_apic_for_gsi(io_apics: &mut [Option<IoApic>], gsi: u32) -> Option<&mut IoApic> {
+    io_apics.iter_mut().flatten().find(|io_apic| {
+        io_apic.gsi_base() <= gsi && gsi < io_apic.gsi_base() + io_apic.redirection_entries()
+    })
+}
+
+/// Maps the register page of an APIC as uncached memory, and returns its virtual address.
+fn map_registers(addr: u64) -> u64 {
+    memory::map_mmio(PhysAddr::new(addr), PAGE_SIZE)
+        .expect("Cannot map the APIC registers.")
+        .as_u64()
+}
+
+fn dispatch_irq(irq: u8) {
+    unsafe {
+        if matches!(CONTROLLER, Some(InterruptController::Pic)) && pic::is_spurious(irq) {
+            return;
+        }
+
+        if let Some(handler) = IRQ_HANDLERS[irq as usize] {
+            handler();
+        }
+
+        match CONTROLLER.as_mut() {
+            Some(InterruptController::Pic) => pic::end_of_interrupt(irq),
+            Some(InterruptController::Apic { local_apic, .. }) => local_apic.end_of_interrupt(),
+            None => {}
+        }
+    }
+}
+
+extern "x86-interrupt" fn irq_stub<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    INTERRUPT_DEPTH.fetch_add(1, Ordering::Relaxed);
+    dispatch_irq(IRQ);
+    INTERRUPT_DEPTH.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// The local APIC does not expect an end of interrupt for spurious interrupts.
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
@@ -0,0 +1,207 @@
+mod apic;
+pub mod irq;
+mod pic;
+
+use crate::gdt;
+use crate::logger::{self, logln};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
+static INTERRUPT_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Generates an exception handler that reports the exception and panics.
+///
+/// The `error_code` variant generates a handler for the exceptions where the CPU pushes an
+/// error code onto the stack.
+macro_rules! exception_handler {
+    ($name:ident, $description:literal) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+            report_exception($description, &stack_frame, None);
+        }
+    };
+    ($name:ident, $description:literal, error_code) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, error_code: u64) {
+            report_exception($description, &stack_frame, Some(error_code));
+        }
+    };
+}
+
+/// Installs the handlers for the CPU exceptions and the IRQs, and loads the IDT.
+///
+/// The GDT must be loaded first, as the double fault, NMI and machine check handlers run on the
+/// interrupt stacks of its TSS.
+///
+/// The vectors reserved by the architecture (9, 15, 22-27 and 31) are never raised by the CPU,
+/// so they are left as non-present entries.
+pub fn initialize_idt() {
+    unsafe {
+        IDT.divide_error.set_handler_fn(divide_error_handler);
+        IDT.debug.set_handler_fn(debug_handler);
+        IDT.non_maskable_interrupt
+            .set_handler_fn(non_maskable_interrupt_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX);
+        IDT.breakpoint.set_handler_fn(breakpoint_handler);
+        IDT.overflow.set_handler_fn(overflow_handler);
+        IDT.bound_range_exceeded
+            .set_handler_fn(bound_range_exceeded_handler);
+        IDT.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        IDT.device_not_available
+            .set_handler_fn(device_not_available_handler);
+        IDT.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        IDT.invalid_tss.set_handler_fn(invalid_tss_handler);
+        IDT.segment_not_present
+            .set_handler_fn(segment_not_present_handler);
+        IDT.stack_segment_fault
+            .set_handler_fn(stack_segment_fault_handler);
+        IDT.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
+        IDT.page_fault.set_handler_fn(page_fault_handler);
+        IDT.x87_floating_point
+            .set_handler_fn(x87_floating_point_handler);
+        IDT.alignment_check.set_handler_fn(alignment_check_handler);
+        IDT.machine_check
+            .set_handler_fn(machine_check_handler)
+            .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        IDT.simd_floating_point
+            .set_handler_fn(simd_floating_point_handler);
+        IDT.virtualization.set_handler_fn(virtualization_handler);
+        IDT.cp_protection_exception
+            .set_handler_fn(cp_protection_exception_handler);
+        IDT.hv_injection_exception
+            .set_handler_fn(hv_injection_exception_handler);
+        IDT.vmm_communication_exception
+            .set_handler_fn(vmm_communication_exception_handler);
+        IDT.security_exception
+            .set_handler_fn(security_exception_handler);
+
+        irq::install_handlers(&mut IDT);
+
+        IDT.load();
+    }
+}
+
+/// Number of interrupt handlers currently running, nested into each other.
+pub fn interrupt_depth() -> usize {
+    INTERRUPT_DEPTH.load(Ordering::Relaxed)
+}
+
+fn report_exception(
+    description: &str,
+    stack_frame: &InterruptStackFrame,
+    error_code: Option<u64>,
+) -> ! {
+    // The exception may have interrupted some logging
+    unsafe { logger::force_unlock() };
+    logln!(
+        "
+------------------------------------------
+
+         CPU EXCEPTION: {description}
+
+------------------------------------------
+"
+    );
+    match error_code {
+        Some(error_code) => logln!("Error code:\t{error_code:#x}"),
+        None => logln!("Error code:\tnone"),
+    }
+    logln!("RIP:\t\t{:#018x}", stack_frame.instruction_pointer.as_u64());
+    logln!("CR2:\t\t{:#018x}", Cr2::read_raw());
+    logln!("{stack_frame:#?}");
+    panic!("Unhandled CPU exception: {description}");
+}
+
+exception_handler!(divide_error_handler, "Divide Error (#DE)");
+exception_handler!(debug_handler, "Debug (#DB)");
+exception_handler!(
+    non_maskable_interrupt_handler,
+    "Non-Maskable Interrupt (NMI)"
+);
+exception_handler!(overflow_handler, "Overflow (#OF)");
+exception_handler!(bound_range_exceeded_handler, "Bound Range Exceeded (#BR)");
+exception_handler!(invalid_opcode_handler, "Invalid Opcode (#UD)");
+exception_handler!(device_not_available_handler, "Device Not Available (#NM)");
+exception_handler!(invalid_tss_handler, "Invalid TSS (#TS)", error_code);
+exception_handler!(
+    segment_not_present_handler,
+    "Segment Not Present (#NP)",
+    error_code
+);
+exception_handler!(
+    stack_segment_fault_handler,
+    "Stack-Segment Fault (#SS)",
+    error_code
+);
+exception_handler!(
+    general_protection_fault_handler,
+    "General Protection Fault (#GP)",
+    error_code
+);
+exception_handler!(
+    x87_floating_point_handler,
+    "x87 Floating-Point Exception (#MF)"
+);
+exception_handler!(alignment_check_handler, "Alignment Check (#AC)", error_code);
+exception_handler!(
+    simd_floating_point_handler,
+    "SIMD Floating-Point Exception (#XM)"
+);
+exception_handler!(virtualization_handler, "Virtualization Exception (#VE)");
+exception_handler!(
+    cp_protection_exception_handler,
+    "Control Protection Exception (#CP)",
+    error_code
+);
+exception_handler!(
+    hv_injection_exception_handler,
+    "Hypervisor Injection Exception (#HV)"
+);
+exception_handler!(
+    vmm_communication_exception_handler,
+    "VMM Communication Exception (#VC)",
+    error_code
+);
+exception_handler!(
+    security_exception_handler,
+    "Security Exception (#SX)",
+    error_code
+);
+
+/// Breakpoints are raised on purpose by `int3`, so execution resumes after reporting them.
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    logln!(
+        "Breakpoint (#BP) at RIP {:#018x}",
+        stack_frame.instruction_pointer.as_u64()
+    );
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    report_exception("Page Fault (#PF)", &stack_frame, Some(error_code.bits()));
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) -> ! {
+    if gdt::is_kernel_stack_guard(Cr2::read_raw())
+        || gdt::is_kernel_stack_guard(stack_frame.stack_pointer.as_u64())
+    {
+        report_exception(
+            "Double Fault (#DF), kernel stack overflow",
+            &stack_frame,
+            Some(error_code),
+        );
+    }
+    report_exception("Double Fault (#DF)", &stack_frame, Some(error_code));
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    report_exception("Machine Check (#MC)", &stack_frame, None);
+}
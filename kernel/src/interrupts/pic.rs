@@ -0,0 +1,107 @@
+use x86_64::instructions::port::Port;
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_1_DATA: u16 = 0x21;
+const PIC_2_COMMAND: u16 = 0xa0;
+const PIC_2_DATA: u16 = 0xa1;
+
+const ICW1_INIT: u8 = 0x11;
+const ICW4_8086: u8 = 0x01;
+const OCW3_READ_ISR: u8 = 0x0b;
+const END_OF_INTERRUPT: u8 = 0x20;
+
+/// The line of the primary PIC the secondary PIC is chained to.
+const CASCADE_IRQ: u8 = 2;
+
+/// Reprograms both 8259 PICs so that IRQs 0-15 are delivered on `vector_offset..vector_offset + 16`
+/// instead of overlapping the CPU exceptions, and masks every line.
+pub fn remap(vector_offset: u8) {
+    unsafe {
+        let mut command_1 = Port::<u8>::new(PIC_1_COMMAND);
+        let mut data_1 = Port::<u8>::new(PIC_1_DATA);
+        let mut command_2 = Port::<u8>::new(PIC_2_COMMAND);
+        let mut data_2 = Port::<u8>::new(PIC_2_DATA);
+
+        command_1.write(ICW1_INIT);
+        io_wait();
+        command_2.write(ICW1_INIT);
+        io_wait();
+        data_1.write(vector_offset);
+        io_wait();
+        data_2.write(vector_offset + 8);
+        io_wait();
+        data_1.write(1 << CASCADE_IRQ);
+        io_wait();
+        data_2.write(CASCADE_IRQ);
+        io_wait();
+        data_1.write(ICW4_8086);
+        io_wait();
+        data_2.write(ICW4_8086);
+        io_wait();
+
+        data_1.write(!(1 << CASCADE_IRQ));
+        data_2.write(0xff);
+    }
+}
+
+/// Masks every line, used once the APIC takes over.
+pub fn disable() {
+    unsafe {
+        Port::<u8>::new(PIC_1_DATA).write(0xff);
+        Port::<u8>::new(PIC_2_DATA).write(0xff);
+    }
+}
+
+pub fn set_masked(irq: u8, masked: bool) {
+    let (mut port, line) = if irq < 8 {
+        (Port::<u8>::new(PIC_1_DATA), irq)
+    } else {
+        (Port::<u8>::new(PIC_2_DATA), irq - 8)
+    };
+    unsafe {
+        let mask = port.read();
+        port.write(if masked {
+            mask | (1 << line)
+        } else {
+            mask & !(1 << line)
+        });
+    }
+}
+
+/// IRQ 7 and 15 are raised spuriously when a line deasserts before the CPU acknowledges it. In
+/// that case the in-service bit is not set and no end of interrupt must be sent, except for the
+/// cascade line of the primary PIC on a spurious IRQ 15.
+pub fn is_spurious(irq: u8) -> bool {
+    match irq {
+        7 => in_service_register() & (1 << 7) == 0,
+        15 if in_service_register() & (1 << 15) == 0 => {
+            unsafe { Port::<u8>::new(PIC_1_COMMAND).write(END_OF_INTERRUPT) };
+            true
+        }
+        _ => false,
+    }
+}
+
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            Port::<u8>::new(PIC_2_COMMAND).write(END_OF_INTERRUPT);
+        }
+        Port::<u8>::new(PIC_1_COMMAND).write(END_OF_INTERRUPT);
+    }
+}
+
+fn in_service_register() -> u16 {
+    unsafe {
+        let mut command_1 = Port::<u8>::new(PIC_1_COMMAND);
+        let mut command_2 = Port::<u8>::new(PIC_2_COMMAND);
+        command_1.write(OCW3_READ_ISR);
+        command_2.write(OCW3_READ_ISR);
+        ((command_2.read() as u16) << 8) | command_1.read() as u16
+    }
+}
+
+/// Writing to an unused port gives the PIC time to process the previous command.
+fn io_wait() {
+    unsafe { Port::<u8>::new(0x80).write(0) };
+}
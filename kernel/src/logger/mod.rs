@@ -1,10 +1,14 @@
+use crate::alloc_sys::oom::register_shrinker;
+use crate::sync::{SpinLock, SpinLockGuard};
+use crate::vga::terminal::Terminal;
+use core::fmt;
 use core::ops::{Deref, DerefMut};
-use core::ptr::NonNull;
 use uart_16550::SerialPort;
 
-static mut LOGGER: Option<Logger> = None;
+static LOGGER: SpinLock<Option<Logger>> = SpinLock::new(None);
 
-/// Log the output to the virtual serial console.
+/// Log the output to the virtual serial console, and to the terminal on the screen once there is
+/// one.
 ///
 /// Omits the '\n' character at the end of all messages.
 ///
@@ -21,6 +25,8 @@ static mut LOGGER: Option<Logger> = None;
 /// }
 /// ```
 ///
+// Only the panic handler uses it, which is not built for the tests
+#[cfg_attr(test, allow(unused_macros))]
 macro_rules! log {
     ($(,)?) => {
         if option_env!("LOGGER_DISABLED").unwrap_or("0") != "1" {
@@ -36,7 +42,8 @@ macro_rules! log {
     };
 }
 
-/// Log the output to the virtual serial console.
+/// Log the output to the virtual serial console, and to the terminal on the screen once there is
+/// one.
 ///
 /// Adds a '\n' character at the end of all messages.
 ///
@@ -68,34 +75,73 @@ macro_rules! logln {
     };
 }
 
+#[cfg_attr(test, allow(unused_imports))]
 pub(crate) use {log, logln};
 
-/// # Safety
-/// no.
+/// Locks the logger until the returned reference is dropped, with interrupts disabled so that
+/// interrupt handlers can log too.
 pub fn logger() -> LoggerRef {
-    unsafe {
-        if (&LOGGER).is_none() {
-            let new_logger = Logger::new();
-            LOGGER = Some(new_logger);
-        }
-        let ptr = ((&mut LOGGER).as_mut().unwrap()) as *mut Logger;
-        LoggerRef {
-            ptr: NonNull::new_unchecked(ptr),
-        }
+    let mut guard = LOGGER.lock_irqsave();
+    if guard.is_none() {
+        *guard = Some(Logger::new());
     }
+    LoggerRef { guard }
+}
+
+/// Shows everything logged from now on in `terminal` too. The output is the same on both, so that
+/// escape sequences color and place it the same way.
+pub fn attach_terminal(terminal: Terminal<'static>) {
+    let attached = logger().terminal.replace(terminal).is_some();
+    if !attached && register_shrinker(release_screen_memory).is_err() {
+        logln!("Cannot register the shrinker of the screen.");
+    }
+}
+
+/// Frees the pixel buffer of the screen of the terminal while it shows text.
+fn release_screen_memory(_needed: usize) -> usize {
+    // Skip it if the allocation comes from the logger itself
+    let Some(mut logger) = LOGGER.try_lock_irqsave() else {
+        return 0;
+    };
+    logger
+        .as_mut()
+        .and_then(|logger| logger.terminal.as_mut())
+        .map_or(0, |terminal| terminal.screen_mut().release_pixel_buffer())
+}
+
+/// Releases the logger, even if something is using it.
+///
+/// # Safety
+/// Only meant for code that never returns to what it interrupted, like the panic handler.
+pub unsafe fn force_unlock() {
+    LOGGER.force_unlock();
 }
 
 const SERIAL_PORT: u16 = 0x3f8;
 
 pub struct Logger {
     port: SerialPort,
+    terminal: Option<Terminal<'static>>,
 }
 
 impl Logger {
     pub fn new() -> Self {
         let mut port = unsafe { SerialPort::new(SERIAL_PORT) };
         port.init();
-        Self { port }
+        Self {
+            port,
+            terminal: None,
+        }
+    }
+}
+
+impl fmt::Write for Logger {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.port.write_str(s)?;
+        if let Some(terminal) = self.terminal.as_mut() {
+            terminal.write_str(s)?;
+        }
+        Ok(())
     }
 }
 
@@ -114,19 +160,19 @@ impl DerefMut for Logger {
 }
 
 pub struct LoggerRef {
-    ptr: NonNull<Logger>,
+    guard: SpinLockGuard<'static, Option<Logger>>,
 }
 
 impl Deref for LoggerRef {
     type Target = Logger;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { self.ptr.as_ref() }
+        self.guard.as_ref().unwrap()
     }
 }
 
 impl DerefMut for LoggerRef {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { self.ptr.as_mut() }
+        self.guard.as_mut().unwrap()
     }
 }
@@ -1,4 +1,6 @@
 #![feature(let_chains)]
+#![feature(abi_x86_interrupt)]
+#![cfg_attr(not(test), feature(alloc_error_handler))]
 #![feature(allocator_api)]
 #![feature(panic_info_message)]
 #![feature(strict_provenance)]
@@ -9,18 +11,27 @@
 
 extern crate alloc;
 
-use crate::alloc_sys::ALLOCATOR;
-use crate::logger::{log, logln};
-use crate::vga::{VgaMode, VgaScreen};
+use crate::alloc_sys::{AllocatorError, ALLOCATOR};
+use crate::logger::logln;
+use crate::memory::frame::FRAME_SIZE;
+use crate::memory::{KernelHeapPages, REGION_SIZE};
+use crate::vga::char::VgaStyle;
+use crate::vga::terminal::Terminal;
+use crate::vga::{VgaError, VgaScreen};
 use bootloader_api::config::Mapping;
 use bootloader_api::info::MemoryRegionKind;
 use bootloader_api::{BootInfo, BootloaderConfig};
 use core::ptr::NonNull;
-use vga::char::VgaStyle;
-use vga::TEXT_SCREEN_ROWS;
+use x86_64::PhysAddr;
 
+mod acpi;
 mod alloc_sys;
+mod gdt;
+mod interrupts;
 mod logger;
+mod memory;
+mod sync;
+mod timer;
 mod utils;
 mod vga;
 
@@ -28,6 +39,7 @@ mod vga;
 static BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
     config.mappings.physical_memory = Some(Mapping::Dynamic);
+    config.kernel_stack_size = gdt::KERNEL_STACK_SIZE;
     config
 };
 
@@ -46,51 +58,114 @@ fn kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
 :)",
     );
 
+    logln!("Initializing GDT...");
+    gdt::initialize_gdt(boot_info.kernel_stack_bottom);
+
+    logln!("Initializing interrupts...");
+    interrupts::initialize_idt();
+
+    logln!("Initializing memory...");
+    unsafe {
+        memory::initialize_memory(
+            &boot_info.memory_regions,
+            boot_info
+                .physical_memory_offset
+                .into_option()
+                .expect("The physical memory is not mapped."),
+        )
+    };
+
     logln!("Initializing allocator...");
-    initialize_allocator(&boot_info);
+    initialize_allocator(boot_info);
+
+    logln!("Initializing IRQs...");
+    interrupts::irq::initialize_irqs(boot_info.rsdp_addr.into_option());
+    x86_64::instructions::interrupts::enable();
+
+    logln!("Initializing timer...");
+    timer::initialize_timer().expect("Cannot initialize timer.");
 
     logln!("Initializing screen...");
     let framebuffer = boot_info
         .framebuffer
         .as_mut()
         .expect("Cannot find Framebuffer, it is None.");
-    let mut screen = VgaScreen::new(framebuffer).expect("Cannot initialize screen.");
+    match VgaScreen::new(framebuffer).and_then(initialize_terminal) {
+        Ok(terminal) => logger::attach_terminal(terminal),
+        Err(error) => {
+            logln!("Cannot initialize the screen: {error:?}");
+            hlt_loop();
+        }
+    }
 
-    logln!("Painting screen...");
-    screen.clear_screen();
-    screen.mode = VgaMode::Text;
+    logln!("\x1b[1mTinyOS Kernel {}\x1b[0m", env!("CARGO_PKG_VERSION"));
+    logln!("Loading OS...");
 
-    screen.print_text(
-        0,
-        0,
-        concat!("TinyOS Kernel ", env!("CARGO_PKG_VERSION")),
-        VgaStyle::default(),
-    );
-    screen.print_text(
+    hlt_loop();
+}
+
+/// Creates the terminal that shows the log, with the copyright footer on the last row of the
+/// screen, below it.
+fn initialize_terminal(screen: VgaScreen<'static>) -> Result<Terminal<'static>, VgaError> {
+    let mut terminal = Terminal::new(screen)?;
+    terminal.reserve_rows(1)?;
+    let footer_row = terminal.rows();
+    terminal.screen_mut().print_text(
         0,
-        TEXT_SCREEN_ROWS - 1,
+        footer_row,
         "© 2024 dcas796 (https://github.com/dcas796)",
         VgaStyle::default(),
     );
-    screen.print_text(0, 1, "Loading OS...", VgaStyle::default());
-
-    hlt_loop();
+    Ok(terminal)
 }
 
+/// Frames that stay with the frame allocator when the usable memory is turned into arenas. They
+/// hold the page tables that are created later on, such as those of MMIO mappings, and back the
+/// growable heap once the arenas are full.
+const FRAME_RESERVE: u64 = 16 * 1024 * 1024 / FRAME_SIZE;
+
+/// Adds the usable memory regions as arenas, except for [`FRAME_RESERVE`], and places the growable
+/// heap behind them for when they are full.
 fn initialize_allocator(boot_info: &BootInfo) {
-    let usable_region = boot_info
+    let frame_allocator = memory::frame_allocator();
+    for region in boot_info
         .memory_regions
         .iter()
-        .find(|m| m.kind == MemoryRegionKind::Usable)
-        .expect("Cannot find suitable free memory.");
-    let start = usable_region.start;
-    let end = usable_region.end;
-    let offset = boot_info.physical_memory_offset.into_option().unwrap_or(0);
-    let size = (end - start) as usize;
-    let ptr = NonNull::new((start + offset) as *mut u8)
-        .expect("Unexpected null pointer when initializing allocator.");
-    unsafe { ptr.write_bytes(0, size) };
-    ALLOCATOR.initialize(ptr, size);
+        .filter(|m| m.kind == MemoryRegionKind::Usable)
+    {
+        let spare_frames = frame_allocator.free_frames().saturating_sub(FRAME_RESERVE);
+        let range = PhysAddr::new(region.start)..PhysAddr::new(region.end);
+        // Some frames of the region may be in use already, such as those of the frame bitmap
+        let Some((first_frame, frames)) = frame_allocator.allocate_largest_in(range, spare_frames)
+        else {
+            continue;
+        };
+        let ptr = memory::phys_to_virt(first_frame.start_address()).as_mut_ptr::<u8>();
+        let size = (frames * FRAME_SIZE) as usize;
+        match ALLOCATOR.add_arena(NonNull::new(ptr).unwrap(), size) {
+            Ok(()) => {}
+            Err(AllocatorError::TooManyArenas) => {
+                frame_allocator.deallocate_contiguous(first_frame, frames);
+                logln!("Too many usable memory regions, ignoring the rest.");
+                break;
+            }
+            Err(_) => frame_allocator.deallocate_contiguous(first_frame, frames),
+        }
+    }
+
+    let region = memory::reserve_region().expect("Cannot reserve virtual memory for the heap.");
+    let ptr = NonNull::new(region.start().as_mut_ptr::<u8>()).unwrap();
+    unsafe {
+        ALLOCATOR
+            .init_heap(ptr, REGION_SIZE as usize, &KernelHeapPages)
+            .expect("Cannot initialize the heap.");
+    }
+    logln!(
+        "Managing {} KiB of heap in {} arena(s), growing from {:#x} when they are full.",
+        ALLOCATOR.capacity() / 1024,
+        ALLOCATOR.arena_count(),
+        region.start().as_u64()
+    );
 }
 
 fn hlt_loop() -> ! {
@@ -102,8 +177,10 @@ fn hlt_loop() -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
-    log!(
-        "
+    x86_64::instructions::interrupts::disable();
+    unsafe { logger::force_unlock() };
+    logger::log!(
+        "\x1b[1;31m
 ------------------------------------------
 
             PANIC IN MY OS :(
@@ -111,7 +188,7 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
 ------------------------------------------
 
 {info}
-"
+\x1b[0m"
     );
     hlt_loop();
 }
@@ -0,0 +1,270 @@
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+use core::ops::Range;
+use core::slice;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+pub const FRAME_SIZE: u64 = 4096;
+
+const BITS_PER_WORD: u64 = u64::BITS as u64;
+
+/// Keeps track of every physical frame with one bit, set while the frame is in use.
+///
+/// The bitmap itself lives in the first usable region big enough to hold it, and is accessed
+/// through the physical memory mapping.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    frame_count: u64,
+    free_frames: u64,
+    usable_frames: u64,
+    next_free: u64,
+}
+
+impl BitmapFrameAllocator {
+    /// # Safety
+    /// `memory_regions` must describe the physical memory accurately, and all of it must be
+    /// mapped at `physical_memory_offset`.
+    pub unsafe fn new(memory_regions: &[MemoryRegion], physical_memory_offset: u64) -> Self {
+        let usable = || {
+            memory_regions
+                .iter()
+                .filter(|region| region.kind == MemoryRegionKind::Usable)
+        };
+        let max_addr = usable().map(|region| region.end).max().unwrap_or(0);
+        let frame_count = max_addr / FRAME_SIZE;
+        let bitmap_words = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_bytes = bitmap_words * size_of::<u64>() as u64;
+
+        let bitmap_region = usable()
+            .find(|region| align_up(region.start) + bitmap_bytes <= region.end)
+            .expect("Cannot find a usable memory region big enough for the frame bitmap.");
+        let bitmap_start = align_up(bitmap_region.start);
+        let bitmap = slice::from_raw_parts_mut(
+            (bitmap_start + physical_memory_offset) as *mut u64,
+            bitmap_words as usize,
+        );
+        bitmap.fill(u64::MAX);
+
+        let mut allocator = Self {
+            bitmap,
+            frame_count,
+            free_frames: 0,
+            usable_frames: 0,
+            next_free: 0,
+        };
+        for region in usable() {
+            for frame in align_up(region.start) / FRAME_SIZE..region.end / FRAME_SIZE {
+                allocator.set_used(frame, false);
+                allocator.usable_frames += 1;
+            }
+        }
+        // Frame 0 is never handed out, so that a null physical address is always invalid.
+        allocator.set_used(0, true);
+        for frame in bitmap_start / FRAME_SIZE..(bitmap_start + bitmap_bytes).div_ceil(FRAME_SIZE) {
+            allocator.set_used(frame, true);
+        }
+        allocator
+    }
+
+    pub fn free_frames(&self) -> u64 {
+        self.free_frames
+    }
+
+    pub fn usable_frames(&self) -> u64 {
+        self.usable_frames
+    }
+
+    pub fn is_used(&self, frame: PhysFrame) -> bool {
+        let index = frame.start_address().as_u64() / FRAME_SIZE;
+        index >= self.frame_count || self.is_index_used(index)
+    }
+
+    /// Allocates `count` physically contiguous frames, and returns the first of them.
+    pub fn allocate_contiguous(&mut self, count: u64) -> Option<PhysFrame> {
+        self.allocate_contiguous_in(
+            PhysAddr::zero()..PhysAddr::new(self.frame_count * FRAME_SIZE),
+            count,
+        )
+    }
+
+    /// Allocates `count` physically contiguous frames within `range`, and returns the first of
+    /// them.
+    pub fn allocate_contiguous_in(
+        &mut self,
+        range: Range<PhysAddr>,
+        count: u64,
+    ) -> Option<PhysFrame> {
+        if count == 0 {
+            return None;
+        }
+        let start = align_up(range.start.as_u64()) / FRAME_SIZE;
+        let end = (range.end.as_u64() / FRAME_SIZE).min(self.frame_count);
+
+        let mut run_start = start;
+        let mut run_len = 0;
+        for index in start..end {
+            if self.is_index_used(index) {
+                run_start = index + 1;
+                run_len = 0;
+                continue;
+            }
+            run_len += 1;
+            if run_len == count {
+                for frame in run_start..run_start + count {
+                    self.set_used(frame, true);
+                }
+                return Some(frame_at(run_start));
+            }
+        }
+        None
+    }
+
+    /// Allocates the longest run of free frames within `range`, but no more than `max` of them,
+    /// and returns the first frame and the length of the run.
+    pub fn allocate_largest_in(
+        &mut self,
+        range: Range<PhysAddr>,
+        max: u64,
+    ) -> Option<(PhysFrame, u64)> {
+        let start = align_up(range.start.as_u64()) / FRAME_SIZE;
+        let end = (range.end.as_u64() / FRAME_SIZE).min(self.frame_count);
+
+        let (mut best_start, mut best_len) = (start, 0);
+        let (mut run_start, mut run_len) = (start, 0);
+        for index in start..end {
+            if best_len == max {
+                break;
+            }
+            if self.is_index_used(index) {
+                run_start = index + 1;
+                run_len = 0;
+                continue;
+            }
+            run_len += 1;
+            if run_len > best_len {
+                (best_start, best_len) = (run_start, run_len);
+            }
+        }
+        if best_len == 0 {
+            return None;
+        }
+        for frame in best_start..best_start + best_len {
+            self.set_used(frame, true);
+        }
+        Some((frame_at(best_start), best_len))
+    }
+
+    /// Returns `count` contiguous frames starting at `first`.
+    pub fn deallocate_contiguous(&mut self, first: PhysFrame, count: u64) {
+        let start = first.start_address().as_u64() / FRAME_SIZE;
+        for index in start..start + count {
+            assert!(
+                self.is_index_used(index),
+                "Double free of physical frame {:#x}",
+                index * FRAME_SIZE
+            );
+            self.set_used(index, false);
+        }
+        self.next_free = self.next_free.min(start);
+    }
+
+    fn is_index_used(&self, index: u64) -> bool {
+        self.bitmap[(index / BITS_PER_WORD) as usize] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, index: u64, used: bool) {
+        if index >= self.frame_count || self.is_index_used(index) == used {
+            return;
+        }
+        let word = &mut self.bitmap[(index / BITS_PER_WORD) as usize];
+        if used {
+            *word |= 1 << (index % BITS_PER_WORD);
+            self.free_frames -= 1;
+        } else {
+            *word &= !(1 << (index % BITS_PER_WORD));
+            self.free_frames += 1;
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let start_word = (self.next_free / BITS_PER_WORD) as usize;
+        let (word_index, word) = self.bitmap[start_word..]
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)
+            .map(|(i, word)| (start_word + i, *word))?;
+        let index = word_index as u64 * BITS_PER_WORD + (!word).trailing_zeros() as u64;
+        if index >= self.frame_count {
+            return None;
+        }
+        self.set_used(index, true);
+        self.next_free = index + 1;
+        Some(frame_at(index))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate_contiguous(frame, 1);
+    }
+}
+
+fn frame_at(index: u64) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index * FRAME_SIZE))
+}
+
+fn align_up(addr: u64) -> u64 {
+    addr.div_ceil(FRAME_SIZE) * FRAME_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::frame::{BitmapFrameAllocator, FRAME_SIZE};
+    use alloc::alloc::alloc;
+    use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+    use core::alloc::Layout;
+    use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+    use x86_64::PhysAddr;
+
+    const MEMORY_SIZE: u64 = 64 * FRAME_SIZE;
+
+    #[test]
+    fn bitmap_frame_allocator_test() {
+        unsafe {
+            let layout = Layout::from_size_align(MEMORY_SIZE as usize, 4096).unwrap();
+            let memory = alloc(layout) as u64;
+            let region = |start: u64, end: u64, kind| MemoryRegion { start, end, kind };
+            let regions = [
+                region(0x1000, 0x20000, MemoryRegionKind::Usable),
+                region(0x20000, 0x30000, MemoryRegionKind::Bootloader),
+                region(0x30000, MEMORY_SIZE, MemoryRegionKind::Usable),
+            ];
+            let mut allocator = BitmapFrameAllocator::new(&regions, memory);
+            assert_eq!(allocator.usable_frames(), 31 + 16);
+            // The bitmap takes the first usable frame
+            assert_eq!(allocator.free_frames(), 30 + 16);
+
+            let frame = allocator.allocate_frame().unwrap();
+            assert_eq!(frame.start_address().as_u64(), 0x2000);
+            assert!(allocator.is_used(frame));
+
+            let range = PhysAddr::new(0x10000)..PhysAddr::new(MEMORY_SIZE);
+            let first = allocator.allocate_contiguous_in(range, 12).unwrap();
+            assert_eq!(first.start_address().as_u64(), 0x10000);
+            assert!(allocator.allocate_contiguous(17).is_none());
+
+            let range = PhysAddr::new(0x1000)..PhysAddr::new(MEMORY_SIZE);
+            let (largest, len) = allocator.allocate_largest_in(range.clone(), 8).unwrap();
+            assert_eq!((largest.start_address().as_u64(), len), (0x3000, 8));
+            let (largest, len) = allocator.allocate_largest_in(range, u64::MAX).unwrap();
+            assert_eq!((largest.start_address().as_u64(), len), (0x30000, 16));
+            allocator.deallocate_contiguous(largest, len);
+
+            allocator.deallocate_frame(frame);
+            assert!(!allocator.is_used(frame));
+            assert_eq!(allocator.allocate_frame(), Some(frame));
+        }
+    }
+}
@@ -0,0 +1,277 @@
+pub mod frame;
+
+use crate::alloc_sys::heap::PageProvider;
+use crate::memory::frame::{BitmapFrameAllocator, FRAME_SIZE};
+use bootloader_api::info::MemoryRegion;
+use core::ptr::NonNull;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::mapper::{
+    FlagUpdateError, MapToError, MapperFlush, TranslateResult, UnmapError,
+};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PageTableIndex, PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+pub const PAGE_SIZE: u64 = FRAME_SIZE;
+/// Each entry of the level 4 page table covers 512 GiB of the virtual address space.
+pub const REGION_SIZE: u64 = 512 * 1024 * 1024 * 1024;
+
+static mut PHYSICAL_MEMORY_OFFSET: u64 = 0;
+static mut FRAME_ALLOCATOR: Option<BitmapFrameAllocator> = None;
+static mut PAGE_TABLE: Option<OffsetPageTable<'static>> = None;
+static mut MMIO_REGION: Option<VirtualRegion> = None;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryError {
+    NotInitialized,
+    FrameAllocationFailed,
+    PageAlreadyMapped(PhysFrame),
+    PageNotMapped,
+    ParentEntryHugePage,
+    InvalidFrameAddress(PhysAddr),
+    VirtualRegionExhausted,
+}
+
+impl From<MapToError<Size4KiB>> for MemoryError {
+    fn from(value: MapToError<Size4KiB>) -> Self {
+        match value {
+            MapToError::FrameAllocationFailed => Self::FrameAllocationFailed,
+            MapToError::ParentEntryHugePage => Self::ParentEntryHugePage,
+            MapToError::PageAlreadyMapped(frame) => Self::PageAlreadyMapped(frame),
+        }
+    }
+}
+
+impl From<UnmapError> for MemoryError {
+    fn from(value: UnmapError) -> Self {
+        match value {
+            UnmapError::ParentEntryHugePage => Self::ParentEntryHugePage,
+            UnmapError::PageNotMapped => Self::PageNotMapped,
+            UnmapError::InvalidFrameAddress(addr) => Self::InvalidFrameAddress(addr),
+        }
+    }
+}
+
+impl From<FlagUpdateError> for MemoryError {
+    fn from(value: FlagUpdateError) -> Self {
+        match value {
+            FlagUpdateError::PageNotMapped => Self::PageNotMapped,
+            FlagUpdateError::ParentEntryHugePage => Self::ParentEntryHugePage,
+        }
+    }
+}
+
+/// A range of virtual addresses reserved for one purpose, handed out from the bottom up.
+#[derive(Debug, Copy, Clone)]
+pub struct VirtualRegion {
+    start: VirtAddr,
+    end: VirtAddr,
+    next: VirtAddr,
+}
+
+impl VirtualRegion {
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    pub fn end(&self) -> VirtAddr {
+        self.end
+    }
+
+    /// Takes the next `pages` pages of the region.
+    pub fn take(&mut self, pages: u64) -> Result<VirtAddr, MemoryError> {
+        let start = self.next;
+        let end = start + pages * PAGE_SIZE;
+        if end > self.end {
+            return Err(MemoryError::VirtualRegionExhausted);
+        }
+        self.next = end;
+        Ok(start)
+    }
+}
+
+/// Sets up the frame allocator and the page table manager on top of the mappings created by the
+/// bootloader.
+///
+/// # Safety
+/// The whole physical memory must be mapped at `physical_memory_offset`, and `memory_regions`
+/// must be the memory map passed by the bootloader.
+pub unsafe fn initialize_memory(memory_regions: &[MemoryRegion], physical_memory_offset: u64) {
+    PHYSICAL_MEMORY_OFFSET = physical_memory_offset;
+    FRAME_ALLOCATOR = Some(BitmapFrameAllocator::new(
+        memory_regions,
+        physical_memory_offset,
+    ));
+
+    let (level_4_frame, _) = Cr3::read();
+    let level_4_table =
+        &mut *(phys_to_virt(level_4_frame.start_address()).as_mut_ptr::<PageTable>());
+    PAGE_TABLE = Some(OffsetPageTable::new(
+        level_4_table,
+        VirtAddr::new(physical_memory_offset),
+    ));
+
+    MMIO_REGION = Some(reserve_region().expect("Cannot reserve a virtual region for MMIO."));
+}
+
+pub fn physical_memory_offset() -> u64 {
+    unsafe { PHYSICAL_MEMORY_OFFSET }
+}
+
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(addr.as_u64() + physical_memory_offset())
+}
+
+pub fn frame_allocator() -> &'static mut BitmapFrameAllocator {
+    unsafe {
+        FRAME_ALLOCATOR
+            .as_mut()
+            .expect("The frame allocator has not been initialized yet.")
+    }
+}
+
+fn page_table() -> Result<&'static mut OffsetPageTable<'static>, MemoryError> {
+    unsafe { PAGE_TABLE.as_mut().ok_or(MemoryError::NotInitialized) }
+}
+
+/// Reserves a whole unused level 4 entry of the higher half, so that it does not collide with
+/// anything the bootloader mapped.
+pub fn reserve_region() -> Result<VirtualRegion, MemoryError> {
+    let page_table = page_table()?;
+    // Indices 256-511 are the higher half. Mark the entry as present so it is not handed out
+    // twice, even before anything is mapped in it.
+    let index = (256..512)
+        .find(|&i| page_table.level_4_table()[i].is_unused())
+        .ok_or(MemoryError::VirtualRegionExhausted)?;
+    let frame = frame_allocator()
+        .allocate_frame()
+        .ok_or(MemoryError::FrameAllocationFailed)?;
+    unsafe {
+        phys_to_virt(frame.start_address())
+            .as_mut_ptr::<PageTable>()
+            .write(PageTable::new());
+    }
+    page_table.level_4_table_mut()[index]
+        .set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+
+    let start = Page::from_page_table_indices(
+        PageTableIndex::new(index as u16),
+        PageTableIndex::new(0),
+        PageTableIndex::new(0),
+        PageTableIndex::new(0),
+    )
+    .start_address();
+    Ok(VirtualRegion {
+        start,
+        end: start + REGION_SIZE,
+        next: start,
+    })
+}
+
+/// Maps `page` to `frame`.
+pub fn map_page(page: Page, frame: PhysFrame, flags: PageTableFlags) -> Result<(), MemoryError> {
+    let page_table = page_table()?;
+    unsafe {
+        page_table
+            .map_to(page, frame, flags, frame_allocator())?
+            .flush();
+    }
+    Ok(())
+}
+
+/// Maps `page` to a newly allocated, zeroed frame.
+pub fn map_anonymous(page: Page, flags: PageTableFlags) -> Result<PhysFrame, MemoryError> {
+    let frame = frame_allocator()
+        .allocate_frame()
+        .ok_or(MemoryError::FrameAllocationFailed)?;
+    unsafe {
+        phys_to_virt(frame.start_address())
+            .as_mut_ptr::<u8>()
+            .write_bytes(0, PAGE_SIZE as usize);
+    }
+    if let Err(error) = map_page(page, frame, flags) {
+        unsafe { frame_allocator().deallocate_frame(frame) };
+        return Err(error);
+    }
+    Ok(frame)
+}
+
+/// Unmaps `page`, and returns the frame it was mapped to.
+pub fn unmap_page(page: Page) -> Result<PhysFrame, MemoryError> {
+    let (frame, flush) = page_table()?.unmap(page)?;
+    flush.flush();
+    Ok(frame)
+}
+
+/// Unmaps `page` and returns its frame to the frame allocator.
+pub fn unmap_and_free(page: Page) -> Result<(), MemoryError> {
+    let frame = unmap_page(page)?;
+    unsafe { frame_allocator().deallocate_frame(frame) };
+    Ok(())
+}
+
+/// Changes the protection flags of an already mapped page.
+pub fn protect(page: Page, flags: PageTableFlags) -> Result<(), MemoryError> {
+    let flush: MapperFlush<Size4KiB> = unsafe { page_table()?.update_flags(page, flags)? };
+    flush.flush();
+    Ok(())
+}
+
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    let page_table = page_table().ok()?;
+    match page_table.translate(addr) {
+        TranslateResult::Mapped { frame, offset, .. } => Some(frame.start_address() + offset),
+        _ => None,
+    }
+}
+
+/// Maps `size` bytes of device memory starting at `addr` as uncached, and returns the virtual
+/// address they can be accessed at.
+pub fn map_mmio(addr: PhysAddr, size: u64) -> Result<VirtAddr, MemoryError> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(addr);
+    let offset = addr - first_frame.start_address();
+    let pages = (offset + size).div_ceil(PAGE_SIZE);
+    let region = unsafe { MMIO_REGION.as_mut().ok_or(MemoryError::NotInitialized)? };
+    let start = region.take(pages)?;
+
+    let flags = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_CACHE
+        | PageTableFlags::WRITE_THROUGH
+        | PageTableFlags::NO_EXECUTE;
+    for i in 0..pages {
+        map_page(
+            Page::containing_address(start + i * PAGE_SIZE),
+            first_frame + i,
+            flags,
+        )?;
+    }
+    Ok(start + offset)
+}
+
+/// Backs the kernel heap with zeroed frames, mapped as writable and not executable.
+pub struct KernelHeapPages;
+
+impl PageProvider for KernelHeapPages {
+    unsafe fn map(&self, ptr: NonNull<u8>, len: usize) -> bool {
+        let start = VirtAddr::from_ptr(ptr.as_ptr());
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        for i in 0..len as u64 / PAGE_SIZE {
+            if map_anonymous(Page::containing_address(start + i * PAGE_SIZE), flags).is_err() {
+                self.unmap(ptr, (i * PAGE_SIZE) as usize);
+                return false;
+            }
+        }
+        true
+    }
+
+    unsafe fn unmap(&self, ptr: NonNull<u8>, len: usize) {
+        let start = VirtAddr::from_ptr(ptr.as_ptr());
+        for i in 0..len as u64 / PAGE_SIZE {
+            unmap_and_free(Page::containing_address(start + i * PAGE_SIZE))
+                .expect("Cannot unmap a heap page.");
+        }
+    }
+}
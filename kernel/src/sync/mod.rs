@@ -0,0 +1,244 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+#[cfg(debug_assertions)]
+use core::panic::Location;
+#[cfg(debug_assertions)]
+use core::ptr::null_mut;
+#[cfg(debug_assertions)]
+use core::sync::atomic::{AtomicPtr, AtomicUsize};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// A fair spinlock: every `lock` takes a ticket, and tickets are served in order.
+///
+/// Locks shared with interrupt handlers must be taken with [`SpinLock::lock_irqsave`], otherwise
+/// an interrupt arriving while the lock is held spins forever. In debug builds, that case panics
+/// instead.
+pub struct SpinLock<T> {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    #[cfg(debug_assertions)]
+    holder: Holder,
+    data: UnsafeCell<T>,
+}
+
+/// Who holds a lock, to report deadlocks.
+#[cfg(debug_assertions)]
+struct Holder {
+    interrupt_depth: AtomicUsize,
+    location: AtomicPtr<Location<'static>>,
+}
+
+#[cfg(debug_assertions)]
+const NO_HOLDER: usize = usize::MAX;
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            #[cfg(debug_assertions)]
+            holder: Holder {
+                interrupt_depth: AtomicUsize::new(NO_HOLDER),
+                location: AtomicPtr::new(null_mut()),
+            },
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    #[track_caller]
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        self.acquire();
+        SpinLockGuard {
+            lock: self,
+            restore_interrupts: false,
+        }
+    }
+
+    /// Disables interrupts, then takes the lock. Interrupts are enabled again when the guard is
+    /// dropped, if they were enabled before.
+    #[track_caller]
+    pub fn lock_irqsave(&self) -> SpinLockGuard<'_, T> {
+        let restore_interrupts = disable_interrupts();
+        self.acquire();
+        SpinLockGuard {
+            lock: self,
+            restore_interrupts,
+        }
+    }
+
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        let ticket = self.now_serving.load(Ordering::Relaxed);
+        self.next_ticket
+            .compare_exchange(
+                ticket,
+                ticket.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()?;
+        self.set_holder();
+        Some(SpinLockGuard {
+            lock: self,
+            restore_interrupts: false,
+        })
+    }
+
+    /// Like [`SpinLock::lock_irqsave`], but gives up instead of waiting if the lock is taken.
+    #[track_caller]
+    pub fn try_lock_irqsave(&self) -> Option<SpinLockGuard<'_, T>> {
+        let restore_interrupts = disable_interrupts();
+        let Some(mut guard) = self.try_lock() else {
+            if restore_interrupts {
+                enable_interrupts();
+            }
+            return None;
+        };
+        guard.restore_interrupts = restore_interrupts;
+        Some(guard)
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.next_ticket.load(Ordering::Relaxed) != self.now_serving.load(Ordering::Relaxed)
+    }
+
+    /// Releases the lock, and drops every pending ticket.
+    ///
+    /// # Safety
+    /// Whoever holds the lock must never touch it again. This is only meant for the panic
+    /// handler, which has to log no matter what it interrupted.
+    pub unsafe fn force_unlock(&self) {
+        self.clear_holder();
+        let next_ticket = self.next_ticket.load(Ordering::Relaxed);
+        self.now_serving.store(next_ticket, Ordering::Release);
+    }
+
+    #[track_caller]
+    fn acquire(&self) {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            #[cfg(debug_assertions)]
+            self.check_reentrancy();
+            core::hint::spin_loop();
+        }
+        self.set_holder();
+    }
+
+    fn release(&self) {
+        self.clear_holder();
+        self.now_serving.fetch_add(1, Ordering::Release);
+    }
+
+    /// There is a single CPU, so a lock held by code with fewer nested interrupts than the
+    /// current one was interrupted by us, and will never be released.
+    #[cfg(debug_assertions)]
+    #[track_caller]
+    fn check_reentrancy(&self) {
+        let depth = crate::interrupts::interrupt_depth();
+        if self.holder.interrupt_depth.load(Ordering::Relaxed) < depth {
+            let location = self.holder.location.load(Ordering::Relaxed);
+            // Nothing else can take the lock from now on
+            unsafe { self.force_unlock() };
+            panic!(
+                "Deadlock: an interrupt handler tried to take a lock at {}, which is held since {} \
+                 by the code it interrupted. Take it with lock_irqsave.",
+                Location::caller(),
+                unsafe { &*location }
+            );
+        }
+    }
+
+    #[track_caller]
+    fn set_holder(&self) {
+        #[cfg(debug_assertions)]
+        {
+            self.holder
+                .location
+                .store(Location::caller() as *const _ as *mut _, Ordering::Relaxed);
+            self.holder
+                .interrupt_depth
+                .store(crate::interrupts::interrupt_depth(), Ordering::Relaxed);
+        }
+    }
+
+    fn clear_holder(&self) {
+        #[cfg(debug_assertions)]
+        self.holder
+            .interrupt_depth
+            .store(NO_HOLDER, Ordering::Relaxed);
+    }
+}
+
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+    restore_interrupts: bool,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.release();
+        if self.restore_interrupts {
+            enable_interrupts();
+        }
+    }
+}
+
+/// Returns whether interrupts were enabled. Host tests run in user mode, where `cli` faults.
+fn disable_interrupts() -> bool {
+    #[cfg(not(test))]
+    {
+        let were_enabled = x86_64::instructions::interrupts::are_enabled();
+        x86_64::instructions::interrupts::disable();
+        were_enabled
+    }
+    #[cfg(test)]
+    false
+}
+
+fn enable_interrupts() {
+    #[cfg(not(test))]
+    x86_64::instructions::interrupts::enable();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sync::SpinLock;
+
+    #[test]
+    fn spin_lock_test() {
+        let lock = SpinLock::new(0);
+        {
+            let mut guard = lock.lock();
+            *guard += 1;
+            assert!(lock.is_locked());
+            assert!(lock.try_lock().is_none());
+        }
+        assert!(!lock.is_locked());
+        *lock.lock_irqsave() += 1;
+        assert_eq!(*lock.try_lock().unwrap(), 2);
+        let guard = lock.try_lock_irqsave().unwrap();
+        assert!(lock.try_lock_irqsave().is_none());
+        drop(guard);
+
+        core::mem::forget(lock.lock());
+        unsafe { lock.force_unlock() };
+        assert_eq!(*lock.lock(), 2);
+    }
+}
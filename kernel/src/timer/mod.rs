@@ -0,0 +1,176 @@
+mod pit;
+
+use crate::interrupts::irq::{register_irq_handler, IrqError};
+use crate::logger::logln;
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+
+pub const TICK_FREQUENCY: u32 = 1000;
+pub const MAX_TIMEOUTS: usize = 32;
+
+const TIMER_IRQ: u8 = 0;
+const TSC_CALIBRATION_TICKS: u64 = 50;
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static NEXT_TIMEOUT_ID: AtomicU64 = AtomicU64::new(0);
+static mut TICK_FREQUENCY_HZ: u64 = 0;
+static mut TSC_FREQUENCY_HZ: u64 = 0;
+static mut TSC_AT_BOOT: u64 = 0;
+static mut TIMEOUTS: [Option<Timeout>; MAX_TIMEOUTS] = [None; MAX_TIMEOUTS];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimerError {
+    IrqError(IrqError),
+    TooManyTimeouts,
+    NotInitialized,
+}
+
+impl From<IrqError> for TimerError {
+    fn from(value: IrqError) -> Self {
+        Self::IrqError(value)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeoutId {
+    slot: usize,
+    id: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Timeout {
+    id: u64,
+    deadline_tick: u64,
+    callback: fn(),
+}
+
+/// Starts the periodic tick and calibrates the TSC against it.
+///
+/// Interrupts must be enabled, as the calibration waits for timer ticks.
+pub fn initialize_timer() -> Result<(), TimerError> {
+    let frequency = pit::set_periodic(TICK_FREQUENCY);
+    unsafe { TICK_FREQUENCY_HZ = frequency as u64 };
+    register_irq_handler(TIMER_IRQ, timer_tick)?;
+
+    let tsc_frequency = calibrate_tsc();
+    unsafe {
+        TSC_AT_BOOT = _rdtsc();
+        TSC_FREQUENCY_HZ = tsc_frequency;
+    }
+    logln!(
+        "Timer ticking at {frequency} Hz, TSC running at {}.{:03} MHz.",
+        tsc_frequency / 1_000_000,
+        tsc_frequency / 1_000 % 1_000
+    );
+    Ok(())
+}
+
+/// Time elapsed since the timer was initialized. It never goes backwards.
+///
+/// Uses the TSC when it could be calibrated, and falls back to counting ticks otherwise.
+pub fn uptime() -> Duration {
+    unsafe {
+        if TSC_FREQUENCY_HZ != 0 {
+            let cycles = _rdtsc().saturating_sub(TSC_AT_BOOT) as u128;
+            let nanos = cycles * NANOS_PER_SEC / TSC_FREQUENCY_HZ as u128;
+            return Duration::from_nanos(nanos as u64);
+        }
+        if TICK_FREQUENCY_HZ == 0 {
+            return Duration::ZERO;
+        }
+        ticks_to_duration(ticks())
+    }
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Halts the CPU until at least `duration` has passed.
+///
+/// If interrupts are disabled, it busy-waits instead, as no tick would wake the CPU up.
+pub fn sleep(duration: Duration) {
+    let deadline = uptime() + duration;
+    while uptime() < deadline {
+        if interrupts::are_enabled() {
+            x86_64::instructions::hlt();
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Runs `callback` once, on the first tick after `delay` has passed.
+///
+/// The callback runs inside the timer interrupt handler, so it must be short and must not wait
+/// for other interrupts.
+pub fn set_timeout(delay: Duration, callback: fn()) -> Result<TimeoutId, TimerError> {
+    let tick_frequency = unsafe { TICK_FREQUENCY_HZ };
+    if tick_frequency == 0 {
+        return Err(TimerError::NotInitialized);
+    }
+    let delay_ticks = (delay.as_nanos() * tick_frequency as u128).div_ceil(NANOS_PER_SEC) as u64;
+    interrupts::without_interrupts(|| unsafe {
+        let (index, slot) = TIMEOUTS
+            .iter_mut()
+            .enumerate()
+            .find(|(_, slot)| slot.is_none())
+            .ok_or(TimerError::TooManyTimeouts)?;
+        let id = NEXT_TIMEOUT_ID.fetch_add(1, Ordering::Relaxed);
+        *slot = Some(Timeout {
+            id,
+            deadline_tick: ticks() + delay_ticks.max(1),
+            callback,
+        });
+        Ok(TimeoutId { slot: index, id })
+    })
+}
+
+/// Cancels a pending timeout. Does nothing if it has already run.
+pub fn cancel_timeout(id: TimeoutId) {
+    interrupts::without_interrupts(|| unsafe {
+        let slot = &mut TIMEOUTS[id.slot];
+        if slot.is_some_and(|timeout| timeout.id == id.id) {
+            *slot = None;
+        }
+    })
+}
+
+fn timer_tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    unsafe {
+        for slot in TIMEOUTS.iter_mut() {
+            match slot {
+                Some(timeout) if timeout.deadline_tick <= now => {
+                    let callback = timeout.callback;
+                    *slot = None;
+                    callback();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Counts the TSC cycles elapsed during a few timer ticks. Returns 0 if the TSC did not advance.
+fn calibrate_tsc() -> u64 {
+    let start_tick = ticks() + 1;
+    while ticks() < start_tick {
+        x86_64::instructions::hlt();
+    }
+    let start = unsafe { _rdtsc() };
+    while ticks() < start_tick + TSC_CALIBRATION_TICKS {
+        x86_64::instructions::hlt();
+    }
+    let cycles = unsafe { _rdtsc() }.saturating_sub(start);
+    let elapsed = ticks_to_duration(TSC_CALIBRATION_TICKS);
+    (cycles as u128 * NANOS_PER_SEC / elapsed.as_nanos()) as u64
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let tick_frequency = unsafe { TICK_FREQUENCY_HZ } as u128;
+    Duration::from_nanos((ticks as u128 * NANOS_PER_SEC / tick_frequency) as u64)
+}
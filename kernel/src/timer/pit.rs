@@ -0,0 +1,23 @@
+use x86_64::instructions::port::Port;
+
+/// Frequency of the oscillator feeding the PIT channels.
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+/// Channel 0, low byte then high byte, mode 2 (rate generator), binary counting.
+const PIT_CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+/// Programs channel 0 to raise IRQ 0 `frequency` times per second, and returns the frequency
+/// actually achieved after rounding the divisor.
+pub fn set_periodic(frequency: u32) -> u32 {
+    let divisor = (PIT_FREQUENCY / frequency).clamp(1, u16::MAX as u32) as u16;
+    unsafe {
+        Port::<u8>::new(PIT_COMMAND).write(PIT_CHANNEL_0_RATE_GENERATOR);
+        let mut channel_0 = Port::<u8>::new(PIT_CHANNEL_0);
+        channel_0.write(divisor as u8);
+        channel_0.write((divisor >> 8) as u8);
+    }
+    PIT_FREQUENCY / divisor as u32
+}
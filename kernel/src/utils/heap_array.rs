@@ -1,7 +1,7 @@
-use alloc::alloc::{alloc, dealloc};
-use core::alloc::Layout;
+use alloc::alloc::{handle_alloc_error, Global};
+use core::alloc::{Allocator, Layout};
 use core::fmt::Debug;
-use core::mem::needs_drop;
+use core::mem::{self, needs_drop, ManuallyDrop};
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::{ptr, slice};
@@ -9,7 +9,8 @@ use core::{ptr, slice};
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HeapArrayError {
     LayoutError(core::alloc::LayoutError),
-    AllocationError,
+    /// The allocator is out of memory, even after running the shrinkers.
+    AllocationError(Layout),
 }
 
 impl From<core::alloc::LayoutError> for HeapArrayError {
@@ -18,22 +19,41 @@ impl From<core::alloc::LayoutError> for HeapArrayError {
     }
 }
 
-pub struct HeapArray<T> {
+pub struct HeapArray<T, A: Allocator = Global> {
     ptr: NonNull<T>,
     len: usize,
+    allocator: A,
 }
 
-impl<T> HeapArray<T> {
-    pub fn new(len: usize) -> Result<Self, HeapArrayError> {
-        let ptr = unsafe {
-            let layout = Layout::array::<T>(len)?;
-            NonNull::new(alloc(layout).cast()).ok_or(HeapArrayError::AllocationError)?
-        };
-        Ok(Self { ptr, len })
+/// The array owns its items, like a `Vec`.
+unsafe impl<T: Send, A: Allocator + Send> Send for HeapArray<T, A> {}
+
+impl<T, A: Allocator> HeapArray<T, A> {
+    /// Allocates an array of `len` uninitialized items from `allocator`.
+    pub fn new(len: usize, allocator: A) -> Result<Self, HeapArrayError> {
+        let layout = Layout::array::<T>(len)?;
+        let ptr = allocator
+            .allocate(layout)
+            .map_err(|_| HeapArrayError::AllocationError(layout))?
+            .cast();
+        Ok(Self {
+            ptr,
+            len,
+            allocator,
+        })
+    }
+
+    /// Wraps an array of `len` items at `ptr`, which is given back to `allocator` on drop.
+    pub const fn new_with_ptr(ptr: NonNull<T>, len: usize, allocator: A) -> Self {
+        Self {
+            ptr,
+            len,
+            allocator,
+        }
     }
 
-    pub const fn new_with_ptr(ptr: NonNull<T>, len: usize) -> Self {
-        Self { ptr, len }
+    pub fn allocator(&self) -> &A {
+        &self.allocator
     }
 
     pub fn ptr(&self) -> *const T {
@@ -65,7 +85,7 @@ impl<T> HeapArray<T> {
     }
 }
 
-impl<T> Drop for HeapArray<T> {
+impl<T, A: Allocator> Drop for HeapArray<T, A> {
     fn drop(&mut self) {
         if needs_drop::<T>() {
             for item in self.iter_mut() {
@@ -77,12 +97,12 @@ impl<T> Drop for HeapArray<T> {
         let layout = Layout::array::<T>(self.len)
             .expect("Could not drop HeapArray<T>: Cannot create the necessary layout for deallocating its base pointer.");
         unsafe {
-            dealloc(self.ptr.as_ptr() as *mut u8, layout);
+            self.allocator.deallocate(self.ptr.cast(), layout);
         }
     }
 }
 
-impl<T> Deref for HeapArray<T> {
+impl<T, A: Allocator> Deref for HeapArray<T, A> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
@@ -90,24 +110,62 @@ impl<T> Deref for HeapArray<T> {
     }
 }
 
-impl<T> DerefMut for HeapArray<T> {
+impl<T, A: Allocator> DerefMut for HeapArray<T, A> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
     }
 }
 
-impl<T: Clone> Clone for HeapArray<T> {
+impl<T: Clone, A: Allocator + Clone> HeapArray<T, A> {
+    pub fn try_clone(&self) -> Result<Self, HeapArrayError> {
+        let mut guard = CloneGuard {
+            array: ManuallyDrop::new(HeapArray::new(self.len, self.allocator.clone())?),
+            written: 0,
+        };
+        for item in self.iter() {
+            unsafe { guard.array.ptr.add(guard.written).write(item.clone()) };
+            guard.written += 1;
+        }
+        let array = unsafe { ManuallyDrop::take(&mut guard.array) };
+        mem::forget(guard);
+        Ok(array)
+    }
+}
+
+/// Frees an array that is being cloned if cloning an item panics, dropping only the items that
+/// were written.
+struct CloneGuard<T, A: Allocator> {
+    array: ManuallyDrop<HeapArray<T, A>>,
+    written: usize,
+}
+
+impl<T, A: Allocator> Drop for CloneGuard<T, A> {
+    fn drop(&mut self) {
+        let array = &mut self.array;
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                array.ptr.as_ptr(),
+                self.written,
+            ));
+            let layout = Layout::array::<T>(array.len).unwrap();
+            array.allocator.deallocate(array.ptr.cast(), layout);
+            ptr::drop_in_place(&mut array.allocator);
+        }
+    }
+}
+
+/// Runs out of memory like `Vec` does, use [`HeapArray::try_clone`] to handle it instead.
+impl<T: Clone, A: Allocator + Clone> Clone for HeapArray<T, A> {
     fn clone(&self) -> Self {
-        let mut new_array =
-            HeapArray::new(self.len).expect("Cannot create a new HeapArray while cloning another.");
-        for (i, item) in self.iter().enumerate() {
-            new_array[i] = item.clone();
+        match self.try_clone() {
+            Ok(array) => array,
+            Err(HeapArrayError::AllocationError(layout)) => handle_alloc_error(layout),
+            Err(HeapArrayError::LayoutError(_)) => panic!("HeapArray too big to be cloned."),
         }
-        new_array
     }
 }
 
-impl<T: Debug> Debug for HeapArray<T> {
+impl<T: Debug, A: Allocator> Debug for HeapArray<T, A> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "[")?;
         for elem in self.iter() {
@@ -117,3 +175,54 @@ impl<T: Debug> Debug for HeapArray<T> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::heap_array::HeapArray;
+    use alloc::alloc::Global;
+    use core::cell::Cell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    /// Counts its drops, and panics when cloned if `fails`.
+    struct Counted<'a> {
+        drops: &'a Cell<usize>,
+        fails: bool,
+    }
+
+    impl Clone for Counted<'_> {
+        fn clone(&self) -> Self {
+            assert!(!self.fails, "Cannot clone");
+            Self {
+                drops: self.drops,
+                fails: false,
+            }
+        }
+    }
+
+    impl Drop for Counted<'_> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    #[test]
+    fn try_clone_panic_test() {
+        let drops = Cell::new(0);
+        let mut array = HeapArray::new(3, Global).unwrap();
+        for (i, item) in array.iter_mut().enumerate() {
+            let item: *mut Counted = item;
+            unsafe {
+                item.write(Counted {
+                    drops: &drops,
+                    fails: i == 2,
+                })
+            };
+        }
+
+        // Only the two clones that were made are dropped
+        assert!(catch_unwind(AssertUnwindSafe(|| array.try_clone())).is_err());
+        assert_eq!(drops.get(), 2);
+        drop(array);
+        assert_eq!(drops.get(), 5);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::utils::heap_array::HeapArray;
+use alloc::alloc::Global;
+use core::alloc::Allocator;
 use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::{ptr, slice};
@@ -15,22 +17,22 @@ impl From<crate::utils::heap_array::HeapArrayError> for HeapVecError {
 }
 
 #[derive(Clone)]
-pub struct HeapVec<T> {
-    array: HeapArray<T>,
+pub struct HeapVec<T, A: Allocator = Global> {
+    array: HeapArray<T, A>,
     len: usize,
 }
 
-impl<T> HeapVec<T> {
-    pub fn new(cap: usize) -> Result<Self, HeapVecError> {
+impl<T, A: Allocator> HeapVec<T, A> {
+    pub fn new(cap: usize, allocator: A) -> Result<Self, HeapVecError> {
         Ok(Self {
-            array: HeapArray::new(cap)?,
+            array: HeapArray::new(cap, allocator)?,
             len: 0,
         })
     }
 
-    pub fn new_with_ptr(ptr: NonNull<T>, cap: usize) -> Self {
+    pub fn new_with_ptr(ptr: NonNull<T>, cap: usize, allocator: A) -> Self {
         Self {
-            array: HeapArray::new_with_ptr(ptr, cap),
+            array: HeapArray::new_with_ptr(ptr, cap, allocator),
             len: 0,
         }
     }
@@ -57,6 +59,7 @@ impl<T> HeapVec<T> {
 
         let result = unsafe { ptr::read(self.array.as_ptr().add(index)) };
         self.array[index..self.len].rotate_left(1);
+        self.len -= 1;
         result
     }
 
@@ -65,11 +68,11 @@ impl<T> HeapVec<T> {
     }
 
     pub fn pop(&mut self) -> T {
-        self.remove(self.len)
+        self.remove(self.len - 1)
     }
 }
 
-impl<T> Deref for HeapVec<T> {
+impl<T, A: Allocator> Deref for HeapVec<T, A> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
@@ -77,8 +80,34 @@ impl<T> Deref for HeapVec<T> {
     }
 }
 
-impl<T> DerefMut for HeapVec<T> {
+impl<T, A: Allocator> DerefMut for HeapVec<T, A> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { slice::from_raw_parts_mut(self.array.ptr_mut(), self.len) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::heap_vec::HeapVec;
+    use alloc::alloc::Global;
+
+    #[test]
+    fn remove_test() {
+        let mut vec = HeapVec::new(4, Global).unwrap();
+        for i in 0..4 {
+            vec.push(i);
+        }
+        assert_eq!(vec.remove(1), 1);
+        assert_eq!(vec.len(), 3);
+        assert_eq!(*vec, [0, 2, 3]);
+
+        assert_eq!(vec.pop(), 3);
+        assert_eq!(vec.pop(), 2);
+        assert_eq!(*vec, [0]);
+        // Removing made room to push again
+        vec.push(5);
+        vec.push(6);
+        vec.push(7);
+        assert_eq!(*vec, [0, 5, 6, 7]);
+    }
+}
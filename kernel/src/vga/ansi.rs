@@ -0,0 +1,278 @@
+use crate::vga::char::VgaStyle;
+use crate::vga::color::VgaColor;
+use noto_sans_mono_bitmap::FontWeight;
+
+/// Parameters kept for each control sequence, the rest are ignored.
+pub const MAX_PARAMS: usize = 16;
+
+/// What to do with a char written to the terminal.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AnsiAction {
+    /// A char to show, or a control char such as `\n` to run.
+    Print(char),
+    /// A complete control sequence, `ESC [ params action`.
+    Csi(CsiSequence),
+    /// `ESC 7`
+    SaveCursor,
+    /// `ESC 8`
+    RestoreCursor,
+    /// `ESC c`
+    Reset,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CsiSequence {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// Whether the parameters start with `?`, as the private sequences of the DEC terminals.
+    pub private: bool,
+    /// The final char, which tells what the sequence does.
+    pub action: char,
+}
+
+impl CsiSequence {
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// The parameter at `index`, or `default` if it is missing or 0.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(&param) if param != 0 => param,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+/// Splits a stream of chars into text and the VT100/ANSI escape sequences in it.
+#[derive(Debug, Clone)]
+pub struct AnsiParser {
+    state: State,
+    sequence: CsiSequence,
+}
+
+impl AnsiParser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            sequence: CsiSequence {
+                params: [0; MAX_PARAMS],
+                len: 0,
+                private: false,
+                action: '\0',
+            },
+        }
+    }
+
+    /// Feeds the next char, and returns what to do once it completes some text or a sequence.
+    pub fn advance(&mut self, char: char) -> Option<AnsiAction> {
+        match (self.state, char) {
+            (_, '\x1b') => {
+                self.state = State::Escape;
+                None
+            }
+            // Cancel the sequence
+            (State::Escape | State::Csi, '\x18' | '\x1a') => {
+                self.state = State::Ground;
+                None
+            }
+            // Control chars still run in the middle of a sequence
+            (_, char) if char.is_ascii_control() => Some(AnsiAction::Print(char)),
+            (State::Ground, char) => Some(AnsiAction::Print(char)),
+            (State::Escape, char) => {
+                self.state = State::Ground;
+                match char {
+                    '[' => {
+                        self.state = State::Csi;
+                        self.sequence.params = [0; MAX_PARAMS];
+                        self.sequence.len = 0;
+                        self.sequence.private = false;
+                        None
+                    }
+                    '7' => Some(AnsiAction::SaveCursor),
+                    '8' => Some(AnsiAction::RestoreCursor),
+                    'c' => Some(AnsiAction::Reset),
+                    _ => None,
+                }
+            }
+            (State::Csi, char) => self.advance_csi(char),
+        }
+    }
+
+    fn advance_csi(&mut self, char: char) -> Option<AnsiAction> {
+        let sequence = &mut self.sequence;
+        match char {
+            '0'..='9' => {
+                if sequence.len == 0 {
+                    sequence.len = 1;
+                }
+                if let Some(param) = sequence.params.get_mut(sequence.len - 1) {
+                    let digit = char as u16 - '0' as u16;
+                    *param = param.saturating_mul(10).saturating_add(digit);
+                }
+            }
+            ';' => sequence.len = (sequence.len.max(1) + 1).min(MAX_PARAMS + 1),
+            '?' if sequence.len == 0 => sequence.private = true,
+            // Intermediate chars, which no supported sequence uses
+            ' '..='/' => {}
+            '@'..='~' => {
+                self.state = State::Ground;
+                sequence.len = sequence.len.min(MAX_PARAMS);
+                sequence.action = char;
+                return Some(AnsiAction::Csi(*sequence));
+            }
+            _ => self.state = State::Ground,
+        }
+        None
+    }
+}
+
+impl Default for AnsiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The color at `index` of the xterm palette: the 16 basic colors, a 6x6x6 color cube and 24
+/// shades of gray.
+pub fn ansi_color(index: u8) -> VgaColor {
+    match index {
+        0 => VgaColor::black(),
+        1 => VgaColor::dark_red(),
+        2 => VgaColor::dark_green(),
+        3 => VgaColor::dark_yellow(),
+        4 => VgaColor::dark_blue(),
+        5 => VgaColor::dark_magenta(),
+        6 => VgaColor::dark_cyan(),
+        7 => VgaColor::light_gray(),
+        8 => VgaColor::dark_gray(),
+        9 => VgaColor::red(),
+        10 => VgaColor::green(),
+        11 => VgaColor::yellow(),
+        12 => VgaColor::blue(),
+        13 => VgaColor::magenta(),
+        14 => VgaColor::cyan(),
+        15 => VgaColor::white(),
+        16..=231 => {
+            let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+            let index = index - 16;
+            VgaColor::new_rgb(level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        232..=255 => {
+            let value = 8 + (index - 232) * 10;
+            VgaColor::new_rgb(value, value, value)
+        }
+    }
+}
+
+/// Applies the Select Graphic Rendition parameters of `ESC [ ... m` to `style`.
+pub fn apply_sgr(mut style: VgaStyle, params: &[u16]) -> VgaStyle {
+    if params.is_empty() {
+        return VgaStyle::default();
+    }
+    let mut i = 0;
+    while i < params.len() {
+        match params[i] {
+            0 => style = VgaStyle::default(),
+            1 => style.weight = FontWeight::Bold,
+            2 => style.weight = FontWeight::Light,
+            22 => style.weight = FontWeight::Regular,
+            param @ 30..=37 => style.foreground = ansi_color((param - 30) as u8),
+            param @ 90..=97 => style.foreground = ansi_color((param - 90 + 8) as u8),
+            39 => style.foreground = VgaStyle::default().foreground,
+            param @ 40..=47 => style.background = ansi_color((param - 40) as u8),
+            param @ 100..=107 => style.background = ansi_color((param - 100 + 8) as u8),
+            49 => style.background = VgaStyle::default().background,
+            38 | 48 => {
+                let Some((color, len)) = extended_color(&params[i + 1..]) else {
+                    break;
+                };
+                if params[i] == 38 {
+                    style.foreground = color;
+                } else {
+                    style.background = color;
+                }
+                i += len;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    style
+}
+
+/// Reads `5;index` or `2;r;g;b`, and returns the color and how many parameters it took.
+fn extended_color(params: &[u16]) -> Option<(VgaColor, usize)> {
+    let byte = |i: usize| params.get(i).map(|&param| param.min(255) as u8);
+    match params.first()? {
+        5 => Some((ansi_color(byte(1)?), 2)),
+        2 => Some((VgaColor::new_rgb(byte(1)?, byte(2)?, byte(3)?), 4)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vga::ansi::{ansi_color, apply_sgr, AnsiAction, AnsiParser};
+    use crate::vga::char::VgaStyle;
+    use crate::vga::color::VgaColor;
+    use alloc::vec::Vec;
+    use noto_sans_mono_bitmap::FontWeight;
+
+    fn parse(text: &str) -> Vec<AnsiAction> {
+        let mut parser = AnsiParser::new();
+        text.chars()
+            .filter_map(|char| parser.advance(char))
+            .collect()
+    }
+
+    #[test]
+    fn parser_test() {
+        let actions = parse("a\x1b[12;;3Hb\x1b[?25l\x1b7\x1b[\x18c\x1b[K\n");
+        assert_eq!(actions[0], AnsiAction::Print('a'));
+        let AnsiAction::Csi(position) = actions[1] else {
+            panic!("Expected a sequence, found {:?}", actions[1]);
+        };
+        assert_eq!((position.action, position.params()), ('H', &[12, 0, 3][..]));
+        assert_eq!(position.param(1, 1), 1);
+        assert_eq!(actions[2], AnsiAction::Print('b'));
+        let AnsiAction::Csi(hide) = actions[3] else {
+            panic!("Expected a sequence, found {:?}", actions[3]);
+        };
+        assert!(hide.private && hide.action == 'l' && hide.params() == [25]);
+        assert_eq!(actions[4], AnsiAction::SaveCursor);
+        // The canceled sequence prints nothing
+        assert_eq!(actions[5], AnsiAction::Print('c'));
+        let AnsiAction::Csi(erase) = actions[6] else {
+            panic!("Expected a sequence, found {:?}", actions[6]);
+        };
+        assert!(erase.params().is_empty() && erase.param(0, 0) == 0);
+        assert_eq!(actions[7], AnsiAction::Print('\n'));
+        assert_eq!(actions.len(), 8);
+    }
+
+    #[test]
+    fn sgr_test() {
+        let style = apply_sgr(VgaStyle::default(), &[1, 31, 44]);
+        assert_eq!(style.weight.val(), FontWeight::Bold.val());
+        assert_eq!(style.foreground, VgaColor::dark_red());
+        assert_eq!(style.background, VgaColor::dark_blue());
+
+        let style = apply_sgr(style, &[22, 38, 5, 196, 48, 2, 1, 2, 3, 39]);
+        assert_eq!(style.weight.val(), FontWeight::Regular.val());
+        assert_eq!(style.foreground, VgaStyle::default().foreground);
+        assert_eq!(style.background, VgaColor::new_rgb(1, 2, 3));
+        assert_eq!(ansi_color(196), VgaColor::new_rgb(255, 0, 0));
+        assert_eq!(ansi_color(244), VgaColor::new_rgb(128, 128, 128));
+
+        assert_eq!(apply_sgr(style, &[]), VgaStyle::default());
+        assert_eq!(apply_sgr(style, &[97, 38, 5]).foreground, VgaColor::white());
+    }
+}
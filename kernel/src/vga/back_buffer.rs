@@ -0,0 +1,242 @@
+use crate::utils::heap_array::{HeapArray, HeapArrayError};
+use crate::vga::color::VgaColor;
+use crate::vga::format::VgaPixelFormat;
+use crate::vga::pixel::VgaPixel;
+use crate::vga::rect::Rect;
+use alloc::alloc::Global;
+
+/// How many separate areas are tracked before they are merged together.
+pub const MAX_DIRTY_RECTS: usize = 16;
+
+/// A copy of the screen in memory, already in the format of the framebuffer, that remembers
+/// which areas changed so that only those are copied to the framebuffer.
+pub struct BackBuffer {
+    bytes: HeapArray<u8>,
+    width: usize,
+    height: usize,
+    format: VgaPixelFormat,
+    dirty: [Rect; MAX_DIRTY_RECTS],
+    dirty_count: usize,
+}
+
+impl BackBuffer {
+    pub fn new(
+        width: usize,
+        height: usize,
+        format: VgaPixelFormat,
+    ) -> Result<Self, HeapArrayError> {
+        let mut bytes = HeapArray::new(width * height * format.bytes_per_pixel(), Global)?;
+        bytes.fill(0);
+        Ok(Self {
+            bytes,
+            width,
+            height,
+            format,
+            dirty: [Rect::default(); MAX_DIRTY_RECTS],
+            dirty_count: 0,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    pub fn format(&self) -> VgaPixelFormat {
+        self.format
+    }
+
+    pub fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty[..self.dirty_count]
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> VgaColor {
+        self.format.read(&self.bytes[self.pos(x, y)..])
+    }
+
+    /// Sets a pixel without marking it as dirty, so that callers drawing a whole area can mark it
+    /// only once with [`BackBuffer::mark_dirty`]. Pixels outside the buffer are ignored.
+    pub fn set(&mut self, x: usize, y: usize, color: VgaColor) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let pos = self.pos(x, y);
+        self.format.write(&mut self.bytes[pos..], color);
+    }
+
+    pub fn fill(&mut self, color: VgaColor) {
+        self.fill_rect(self.bounds(), color);
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: VgaColor) {
+        let rect = rect.intersection(&self.bounds());
+        if rect.is_empty() {
+            return;
+        }
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        let mut pixel = [0; 4];
+        self.format.write(&mut pixel, color);
+        for y in rect.y..rect.bottom() {
+            let row = self.pos(rect.x, y)..self.pos(rect.right(), y);
+            for chunk in self.bytes[row].chunks_exact_mut(bytes_per_pixel) {
+                chunk.copy_from_slice(&pixel[..bytes_per_pixel]);
+            }
+        }
+        self.mark_dirty(rect);
+    }
+
+    /// Copies `count` rows starting at row `from` to row `to`, such as to scroll, and marks the
+    /// copied rows as dirty.
+    pub fn move_rows(&mut self, from: usize, to: usize, count: usize) {
+        let count = count.min(self.height.saturating_sub(from.max(to)));
+        if count == 0 {
+            return;
+        }
+        let bytes = self.pos(0, from)..self.pos(0, from + count);
+        let dest = self.pos(0, to);
+        self.bytes.copy_within(bytes, dest);
+        self.mark_dirty(Rect::new(0, to, self.width, count));
+    }
+
+    /// Writes `pixels` to the row `y`, and marks as dirty only the span that changed.
+    pub fn update_row(&mut self, y: usize, pixels: &[VgaPixel]) {
+        if y >= self.height {
+            return;
+        }
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        let mut changed: Option<(usize, usize)> = None;
+        let mut encoded = [0; 4];
+        for (x, pixel) in pixels.iter().take(self.width).enumerate() {
+            self.format.write(&mut encoded, pixel.0);
+            let pos = self.pos(x, y);
+            let current = &mut self.bytes[pos..pos + bytes_per_pixel];
+            if *current != encoded[..bytes_per_pixel] {
+                current.copy_from_slice(&encoded[..bytes_per_pixel]);
+                changed = Some(changed.map_or((x, x), |(first, _)| (first, x)));
+            }
+        }
+        if let Some((first, last)) = changed {
+            self.mark_dirty(Rect::new(first, y, last - first + 1, 1));
+        }
+    }
+
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        let rect = rect.intersection(&self.bounds());
+        if rect.is_empty()
+            || self
+                .dirty_rects()
+                .iter()
+                .any(|dirty| dirty.contains_rect(&rect))
+        {
+            return;
+        }
+        if self.dirty_count < MAX_DIRTY_RECTS {
+            self.dirty[self.dirty_count] = rect;
+            self.dirty_count += 1;
+            return;
+        }
+        // Merge it with the rect that grows the least
+        let closest = self
+            .dirty
+            .iter_mut()
+            .min_by_key(|dirty| dirty.union(&rect).area() - dirty.area())
+            .unwrap();
+        *closest = closest.union(&rect);
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty[0] = self.bounds();
+        self.dirty_count = 1;
+    }
+
+    /// Copies the dirty areas to `framebuffer`, row by row, and forgets them.
+    pub fn present(&mut self, framebuffer: &mut [u8], stride: usize) {
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        for rect in &self.dirty[..self.dirty_count] {
+            let len = rect.width * bytes_per_pixel;
+            for y in rect.y..rect.bottom() {
+                let from = self.pos(rect.x, y);
+                let to = (y * stride + rect.x) * bytes_per_pixel;
+                framebuffer[to..to + len].copy_from_slice(&self.bytes[from..from + len]);
+            }
+        }
+        self.dirty_count = 0;
+    }
+
+    fn pos(&self, x: usize, y: usize) -> usize {
+        (y * self.width + x) * self.format.bytes_per_pixel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vga::back_buffer::{BackBuffer, MAX_DIRTY_RECTS};
+    use crate::vga::color::VgaColor;
+    use crate::vga::format::VgaPixelFormat;
+    use crate::vga::pixel::VgaPixel;
+    use crate::vga::rect::Rect;
+    use alloc::vec;
+    use bootloader_api::info::PixelFormat;
+
+    #[test]
+    fn dirty_rects_test() {
+        let format = VgaPixelFormat::new(PixelFormat::Rgb, 3).unwrap();
+        let mut buffer = BackBuffer::new(8, 4, format).unwrap();
+        let stride = 10;
+        let mut framebuffer = vec![0xaa; stride * 4 * 3];
+
+        // Nothing is copied until something changes
+        buffer.present(&mut framebuffer, stride);
+        assert!(framebuffer.iter().all(|&byte| byte == 0xaa));
+
+        buffer.fill_rect(Rect::new(6, 2, 5, 5), VgaColor::white());
+        assert_eq!(buffer.dirty_rects(), [Rect::new(6, 2, 2, 2)]);
+        buffer.mark_dirty(Rect::new(7, 3, 1, 1));
+        assert_eq!(buffer.dirty_rects().len(), 1);
+        buffer.present(&mut framebuffer, stride);
+        assert!(buffer.dirty_rects().is_empty());
+        for y in 0..4 {
+            for x in 0..stride {
+                let expected = if (6..8).contains(&x) && y >= 2 {
+                    0xff
+                } else {
+                    0xaa
+                };
+                let pos = (y * stride + x) * 3;
+                assert_eq!(framebuffer[pos..pos + 3], [expected; 3], "({x}, {y})");
+            }
+        }
+
+        // Only the pixels that changed are marked
+        let mut row = [VgaPixel(VgaColor::black()); 8];
+        row[6] = VgaPixel(VgaColor::white());
+        row[7] = VgaPixel(VgaColor::white());
+        buffer.update_row(2, &row);
+        assert!(buffer.dirty_rects().is_empty());
+        row[1] = VgaPixel(VgaColor::white());
+        row[6] = VgaPixel(VgaColor::black());
+        buffer.update_row(2, &row);
+        assert_eq!(buffer.dirty_rects(), [Rect::new(1, 2, 6, 1)]);
+        assert_eq!(buffer.get(1, 2), VgaColor::white());
+
+        // Too many rects get merged instead of dropped
+        for i in 0..MAX_DIRTY_RECTS * 2 {
+            buffer.mark_dirty(Rect::new(i % 8, i / 8, 1, 1));
+        }
+        assert_eq!(buffer.dirty_rects().len(), MAX_DIRTY_RECTS);
+        for i in 0..MAX_DIRTY_RECTS * 2 {
+            let pixel = Rect::new(i % 8, i / 8, 1, 1);
+            assert!(buffer
+                .dirty_rects()
+                .iter()
+                .any(|rect| rect.contains_rect(&pixel)));
+        }
+    }
+}
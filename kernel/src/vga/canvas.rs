@@ -0,0 +1,496 @@
+use crate::vga::color::{BlendMode, VgaColor};
+use crate::vga::pixel::VgaPixel;
+use crate::vga::rect::Rect;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The radii of ellipses are capped to this, so that their math fits in a `u128`. It is still far
+/// bigger than any screen.
+const MAX_RADIUS: usize = 1 << 62;
+
+/// Draws shapes and bitmaps onto rows of pixels, such as the pixel buffer of a
+/// [`VgaScreen`](crate::vga::VgaScreen).
+///
+/// Points are signed so that shapes can be partly off the canvas. Only the pixels inside the
+/// clipping rect are drawn, mixed with the ones below them with the blend mode.
+pub struct Canvas<'b> {
+    pixels: &'b mut [VgaPixel],
+    width: usize,
+    height: usize,
+    clip: Rect,
+    blend_mode: BlendMode,
+}
+
+impl<'b> Canvas<'b> {
+    /// Creates a canvas over `pixels`, in rows of `width` pixels.
+    pub fn new(pixels: &'b mut [VgaPixel], width: usize) -> Self {
+        let height = pixels.len().checked_div(width).unwrap_or(0);
+        Self {
+            pixels,
+            width,
+            height,
+            clip: Rect::new(0, 0, width, height),
+            blend_mode: BlendMode::Replace,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    pub fn clip(&self) -> Rect {
+        self.clip
+    }
+
+    /// Only draws inside `clip` from now on.
+    pub fn set_clip(&mut self, clip: Rect) {
+        self.clip = clip.intersection(&self.bounds());
+    }
+
+    pub fn reset_clip(&mut self) {
+        self.clip = self.bounds();
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Mixes what is drawn from now on with the pixels below it with `blend_mode`.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    /// The color at `(x, y)`, or `None` if it is off the canvas.
+    pub fn get(&self, x: isize, y: isize) -> Option<VgaColor> {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return None;
+        }
+        Some(self.pixels[y as usize * self.width + x as usize].0)
+    }
+
+    pub fn set_pixel(&mut self, x: isize, y: isize, color: VgaColor) {
+        if self.is_clipped(x, y) {
+            return;
+        }
+        let index = y as usize * self.width + x as usize;
+        self.pixels[index] = VgaPixel(self.pixels[index].0.mixing(color, self.blend_mode));
+    }
+
+    /// Fills the clipping rect with `color`.
+    pub fn fill(&mut self, color: VgaColor) {
+        self.fill_rect(self.clip, color);
+    }
+
+    /// Draws a line from `(x0, y0)` to `(x1, y1)` with Bresenham's algorithm.
+    pub fn line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: VgaColor) {
+        self.draw_line((x0, y0), (x1, y1), color);
+        self.set_pixel(x1, y1, color);
+    }
+
+    /// Draws the edges of `rect`, inside of it.
+    pub fn rect(&mut self, rect: Rect, color: VgaColor) {
+        if rect.is_empty() {
+            return;
+        }
+        let (left, top) = (rect.x as isize, rect.y as isize);
+        let right = rect.right() as isize - 1;
+        let bottom = rect.bottom() as isize - 1;
+        self.span(left, right, top, color);
+        if bottom > top {
+            self.span(left, right, bottom, color);
+        }
+        for y in top + 1..bottom {
+            self.set_pixel(left, y, color);
+            if right > left {
+                self.set_pixel(right, y, color);
+            }
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: VgaColor) {
+        let rect = rect.intersection(&self.clip);
+        for y in rect.y..rect.bottom() {
+            self.span(
+                rect.x as isize,
+                rect.right() as isize - 1,
+                y as isize,
+                color,
+            );
+        }
+    }
+
+    pub fn circle(&mut self, x: isize, y: isize, radius: usize, color: VgaColor) {
+        self.ellipse(x, y, radius, radius, color);
+    }
+
+    pub fn fill_circle(&mut self, x: isize, y: isize, radius: usize, color: VgaColor) {
+        self.fill_ellipse(x, y, radius, radius, color);
+    }
+
+    /// Draws the outline of the ellipse centered at `(x, y)` with the radii `radius_x` and
+    /// `radius_y`, row by row so that every pixel is drawn once.
+    pub fn ellipse(
+        &mut self,
+        x: isize,
+        y: isize,
+        radius_x: usize,
+        radius_y: usize,
+        color: VgaColor,
+    ) {
+        let (radius_x, radius_y) = (radius_x.min(MAX_RADIUS), radius_y.min(MAX_RADIUS));
+        for (row, offset) in self.ellipse_rows(y, radius_y) {
+            let width = Self::ellipse_width(radius_x, radius_y, offset);
+            // Cover the pixels up to the next row out, so that the outline is connected
+            let next = if offset < radius_y {
+                Self::ellipse_width(radius_x, radius_y, offset + 1)
+            } else {
+                -1
+            };
+            let start = (next + 1).min(width);
+            if start <= 0 {
+                self.span(x.saturating_sub(width), x.saturating_add(width), row, color);
+            } else {
+                self.span(x.saturating_add(start), x.saturating_add(width), row, color);
+                self.span(x.saturating_sub(width), x.saturating_sub(start), row, color);
+            }
+        }
+    }
+
+    pub fn fill_ellipse(
+        &mut self,
+        x: isize,
+        y: isize,
+        radius_x: usize,
+        radius_y: usize,
+        color: VgaColor,
+    ) {
+        let (radius_x, radius_y) = (radius_x.min(MAX_RADIUS), radius_y.min(MAX_RADIUS));
+        for (row, offset) in self.ellipse_rows(y, radius_y) {
+            let width = Self::ellipse_width(radius_x, radius_y, offset);
+            self.span(x.saturating_sub(width), x.saturating_add(width), row, color);
+        }
+    }
+
+    /// Draws lines between the consecutive `points` and from the last one back to the first.
+    pub fn polygon(&mut self, points: &[(isize, isize)], color: VgaColor) {
+        if let [(x, y)] = points {
+            self.set_pixel(*x, *y, color);
+            return;
+        }
+        for (i, &from) in points.iter().enumerate() {
+            // Every line stops before its end, which is where the next one starts
+            self.draw_line(from, points[(i + 1) % points.len()], color);
+        }
+    }
+
+    /// Fills the inside of the polygon with the corners at `points`, with the even-odd rule.
+    /// Like a [`Rect`], the right and bottom edges are not drawn, so that polygons that share an
+    /// edge do not overlap.
+    pub fn fill_polygon(&mut self, points: &[(isize, isize)], color: VgaColor) {
+        let Some(top) = points.iter().map(|&(_, y)| y).min() else {
+            return;
+        };
+        let bottom = points.iter().map(|&(_, y)| y).max().unwrap();
+        let top = top.max(self.clip.y as isize);
+        let bottom = bottom.min(self.clip.bottom() as isize);
+
+        let mut crossings = Vec::new();
+        for y in top..bottom {
+            crossings.clear();
+            for (i, &(x0, y0)) in points.iter().enumerate() {
+                let (x1, y1) = points[(i + 1) % points.len()];
+                let ((x0, y0), (x1, y1)) = if y0 < y1 {
+                    ((x0, y0), (x1, y1))
+                } else {
+                    ((x1, y1), (x0, y0))
+                };
+                if y0 <= y && y < y1 {
+                    crossings.push(x0 + ((y - y0) * (x1 - x0)).div_euclid(y1 - y0));
+                }
+            }
+            crossings.sort_unstable();
+            for pair in crossings.chunks_exact(2) {
+                self.span(pair[0], pair[1] - 1, y, color);
+            }
+        }
+    }
+
+    /// Fills the area of pixels around `(x, y)` that have the same color as it, up to the edges
+    /// of the clipping rect.
+    pub fn flood_fill(&mut self, x: isize, y: isize, color: VgaColor) {
+        if self.is_clipped(x, y) {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let target = self.pixels[y * self.width + x].0;
+        // Every pixel of the area is the same, so they all blend to the same color
+        let fill = VgaPixel(target.mixing(color, self.blend_mode));
+        if fill.0 == target {
+            return;
+        }
+
+        let clip = self.clip;
+        let width = self.width;
+        let is_target = |pixels: &[VgaPixel], x: usize, y: usize| pixels[y * width + x].0 == target;
+        let mut seeds = vec![(x, y)];
+        while let Some((x, y)) = seeds.pop() {
+            if !is_target(self.pixels, x, y) {
+                continue;
+            }
+            let mut left = x;
+            while left > clip.x && is_target(self.pixels, left - 1, y) {
+                left -= 1;
+            }
+            let mut right = x + 1;
+            while right < clip.right() && is_target(self.pixels, right, y) {
+                right += 1;
+            }
+            self.pixels[y * width + left..y * width + right].fill(fill);
+
+            // Add a seed for every run of the area in the rows above and below
+            for row in [y.wrapping_sub(1), y + 1] {
+                if row < clip.y || row >= clip.bottom() {
+                    continue;
+                }
+                for x in left..right {
+                    let starts_run = x == left || !is_target(self.pixels, x - 1, row);
+                    if starts_run && is_target(self.pixels, x, row) {
+                        seeds.push((x, row));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws `bitmap`, in rows of `width` pixels, with its top left corner at `(x, y)`. The
+    /// pixels of `color_key` are left out, to draw sprites that are not rectangles.
+    pub fn blit(
+        &mut self,
+        x: isize,
+        y: isize,
+        width: usize,
+        bitmap: &[VgaPixel],
+        color_key: Option<VgaColor>,
+    ) {
+        if width == 0 {
+            return;
+        }
+        for (row, pixels) in bitmap.chunks(width).enumerate() {
+            for (col, pixel) in pixels.iter().enumerate() {
+                if color_key != Some(pixel.0) {
+                    self.set_pixel(x + col as isize, y + row as isize, pixel.0);
+                }
+            }
+        }
+    }
+
+    fn is_clipped(&self, x: isize, y: isize) -> bool {
+        x < self.clip.x as isize
+            || y < self.clip.y as isize
+            || x >= self.clip.right() as isize
+            || y >= self.clip.bottom() as isize
+    }
+
+    /// Draws the pixels of row `y` from `x0` to `x1`, both included.
+    fn span(&mut self, x0: isize, x1: isize, y: isize, color: VgaColor) {
+        if y < self.clip.y as isize || y >= self.clip.bottom() as isize {
+            return;
+        }
+        let x0 = x0.max(self.clip.x as isize);
+        let x1 = x1.min(self.clip.right() as isize - 1);
+        if x1 < x0 {
+            return;
+        }
+        let start = y as usize * self.width;
+        for pixel in &mut self.pixels[start + x0 as usize..=start + x1 as usize] {
+            *pixel = VgaPixel(pixel.0.mixing(color, self.blend_mode));
+        }
+    }
+
+    /// Draws a line with Bresenham's algorithm, leaving out its last pixel.
+    fn draw_line(&mut self, (x0, y0): (isize, isize), (x1, y1): (isize, isize), color: VgaColor) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+        while (x, y) != (x1, y1) {
+            self.set_pixel(x, y, color);
+            let double_error = 2 * error;
+            if double_error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if double_error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// How far the ellipse reaches to each side of its center at `offset` rows from it, rounding
+    /// the radii up by half a pixel like the midpoint algorithm does.
+    fn ellipse_width(radius_x: usize, radius_y: usize, offset: usize) -> isize {
+        let a = 2 * radius_x as u128 + 1;
+        let b = 2 * radius_y as u128 + 1;
+        let offset = 2 * offset as u128;
+        let rest = b * b - offset * offset;
+        let width = match (a * a).checked_mul(rest) {
+            Some(product) => product.isqrt() / (2 * b),
+            // Taking the root first rounds a bit more, but only for radii far bigger than a screen
+            None => rest.isqrt() * a / (2 * b),
+        };
+        width as isize
+    }
+
+    /// The rows of the clipping rect that an ellipse centered at row `y` covers, with how far each
+    /// one is from it.
+    fn ellipse_rows(&self, y: isize, radius_y: usize) -> impl Iterator<Item = (isize, usize)> {
+        let top = y
+            .saturating_sub(radius_y as isize)
+            .max(self.clip.y as isize);
+        let bottom = y
+            .saturating_add(radius_y as isize)
+            .min(self.clip.bottom() as isize - 1);
+        (top..=bottom).map(move |row| (row, row.abs_diff(y)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vga::canvas::Canvas;
+    use crate::vga::color::{BlendMode, VgaColor};
+    use crate::vga::pixel::VgaPixel;
+    use crate::vga::rect::Rect;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 12;
+
+    fn count(canvas: &Canvas, color: VgaColor) -> usize {
+        let mut count = 0;
+        for y in 0..canvas.height() as isize {
+            for x in 0..canvas.width() as isize {
+                if canvas.get(x, y) == Some(color) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn line_test() {
+        let mut pixels = vec![VgaPixel(VgaColor::black()); WIDTH * HEIGHT];
+        let mut canvas = Canvas::new(&mut pixels, WIDTH);
+        let white = VgaColor::white();
+        canvas.line(1, 1, 9, 5, white);
+        assert_eq!(canvas.get(1, 1), Some(white));
+        assert_eq!(canvas.get(9, 5), Some(white));
+        assert_eq!(canvas.get(5, 3), Some(white));
+        assert_eq!(count(&canvas, white), 9);
+
+        // Off the canvas and outside the clipping rect
+        canvas.fill(VgaColor::black());
+        canvas.set_clip(Rect::new(0, 0, 4, 100));
+        canvas.line(-5, 0, 100, 0, white);
+        assert_eq!(count(&canvas, white), 4);
+
+        canvas.reset_clip();
+        canvas.polygon(&[(0, 0), (3, 0), (3, 3)], VgaColor::red());
+        assert_eq!(count(&canvas, VgaColor::red()), 9);
+    }
+
+    #[test]
+    fn shapes_test() {
+        let mut pixels = vec![VgaPixel(VgaColor::black()); WIDTH * HEIGHT];
+        let mut canvas = Canvas::new(&mut pixels, WIDTH);
+        let white = VgaColor::white();
+        canvas.rect(Rect::new(2, 2, 5, 4), white);
+        assert_eq!(count(&canvas, white), 14);
+        canvas.fill_rect(Rect::new(10, 8, 100, 100), white);
+        assert_eq!(count(&canvas, white), 14 + 6 * 4);
+
+        canvas.fill(VgaColor::black());
+        canvas.fill_polygon(&[(1, 1), (5, 1), (5, 4), (1, 4)], white);
+        assert_eq!(count(&canvas, white), 4 * 3);
+
+        canvas.fill(VgaColor::black());
+        canvas.fill_circle(7, 5, 3, white);
+        for (x, y) in [(4, 5), (10, 5), (7, 2), (7, 8), (7, 5)] {
+            assert_eq!(canvas.get(x, y), Some(white));
+        }
+        assert_eq!(canvas.get(4, 2), Some(VgaColor::black()));
+        let filled = count(&canvas, white);
+        canvas.fill(VgaColor::black());
+        canvas.circle(7, 5, 3, white);
+        let outline = count(&canvas, white);
+        assert!(outline < filled);
+        for (x, y) in [(4, 5), (10, 5), (7, 2), (7, 8)] {
+            assert_eq!(canvas.get(x, y), Some(white));
+        }
+        assert_eq!(canvas.get(7, 5), Some(VgaColor::black()));
+
+        // Huge ellipses only reach the rows of the canvas
+        canvas.fill(VgaColor::black());
+        canvas.fill_circle(-(1 << 40), 5, (1 << 40) + 3, white);
+        assert_eq!(canvas.get(3, 5), Some(white));
+        assert_eq!(canvas.get(4, 5), Some(VgaColor::black()));
+        canvas.ellipse(0, 0, usize::MAX, usize::MAX, white);
+        canvas.fill_ellipse(isize::MAX, isize::MIN, usize::MAX, 3, white);
+
+        // Every pixel of the outline is drawn once, so it is half opaque everywhere
+        canvas.fill(VgaColor::black());
+        canvas.ellipse(7, 5, 6, 2, white);
+        let outline = count(&canvas, white);
+        canvas.fill(VgaColor::black());
+        canvas.set_blend_mode(BlendMode::Alpha(128));
+        canvas.ellipse(7, 5, 6, 2, white);
+        let half = VgaColor::black().interpolate(white, 128);
+        assert_eq!(count(&canvas, half), outline);
+    }
+
+    #[test]
+    fn flood_fill_test() {
+        let mut pixels = vec![VgaPixel(VgaColor::black()); WIDTH * HEIGHT];
+        let mut canvas = Canvas::new(&mut pixels, WIDTH);
+        let white = VgaColor::white();
+        canvas.rect(Rect::new(2, 2, 6, 5), white);
+        canvas.flood_fill(4, 4, VgaColor::red());
+        assert_eq!(count(&canvas, VgaColor::red()), 4 * 3);
+
+        canvas.set_clip(Rect::new(0, 0, WIDTH, 8));
+        canvas.flood_fill(0, 0, VgaColor::blue());
+        assert_eq!(count(&canvas, VgaColor::blue()), WIDTH * 8 - 6 * 5);
+    }
+
+    #[test]
+    fn blit_test() {
+        let mut pixels = vec![VgaPixel(VgaColor::black()); WIDTH * HEIGHT];
+        let mut canvas = Canvas::new(&mut pixels, WIDTH);
+        let key = VgaColor::new_rgb(255, 0, 255);
+        let sprite: Vec<VgaPixel> = [key, VgaColor::red(), VgaColor::red(), key]
+            .into_iter()
+            .map(VgaPixel)
+            .collect();
+        canvas.fill(VgaColor::blue());
+        canvas.blit(-1, 3, 2, &sprite, Some(key));
+        assert_eq!(canvas.get(0, 3), Some(VgaColor::red()));
+        assert_eq!(canvas.get(0, 4), Some(VgaColor::blue()));
+        assert_eq!(count(&canvas, VgaColor::red()), 1);
+
+        canvas.set_blend_mode(BlendMode::Alpha(0));
+        canvas.blit(5, 5, 2, &sprite, None);
+        assert_eq!(count(&canvas, VgaColor::red()), 1);
+    }
+}
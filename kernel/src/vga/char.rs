@@ -16,6 +16,11 @@ impl VgaStyle {
             weight,
         }
     }
+
+    /// The same style with the background and foreground colors swapped.
+    pub fn inverted(self) -> Self {
+        Self::new(self.foreground, self.background, self.weight)
+    }
 }
 
 impl PartialEq for VgaStyle {
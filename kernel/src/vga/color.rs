@@ -147,9 +147,32 @@ impl VgaColor {
             Self::hsl_to_rgb(self.hue, self.saturation, self.lightness);
     }
 
+    /// Draws `other` over `self` with `blend_mode`.
     pub fn mixing(self, other: VgaColor, blend_mode: BlendMode) -> VgaColor {
         match blend_mode {
+            BlendMode::Replace => other,
             BlendMode::Average => self.blend_average(other),
+            BlendMode::Alpha(alpha) => self.interpolate(other, alpha),
+        }
+    }
+
+    /// Goes from `self` at an `amount` of 0 to `other` at 255, such as to draw the edges of a
+    /// glyph with their coverage.
+    pub fn interpolate(self, other: VgaColor, amount: u8) -> VgaColor {
+        match amount {
+            0 => self,
+            255 => other,
+            _ => {
+                let amount = amount as u32;
+                let mix = |from: u8, to: u8| {
+                    ((from as u32 * (255 - amount) + to as u32 * amount) / 255) as u8
+                };
+                VgaColor::new_rgb(
+                    mix(self.red, other.red),
+                    mix(self.green, other.green),
+                    mix(self.blue, other.blue),
+                )
+            }
         }
     }
 
@@ -186,7 +209,7 @@ impl VgaColor {
     }
 
     pub fn dark_blue() -> Self {
-        Self::new_rgb(127, 0, 0)
+        Self::new_rgb(0, 0, 127)
     }
 
     pub fn dark_magenta() -> Self {
@@ -236,5 +259,23 @@ impl VgaColor {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlendMode {
+    /// Draws the color as is, hiding what was below it.
+    Replace,
     Average,
+    /// Draws the color with an opacity from 0, which is invisible, to 255, which is opaque.
+    Alpha(u8),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vga::color::VgaColor;
+
+    #[test]
+    fn interpolate_test() {
+        let from = VgaColor::new_rgb(0, 100, 255);
+        let to = VgaColor::new_rgb(255, 200, 0);
+        assert_eq!(from.interpolate(to, 0), from);
+        assert_eq!(from.interpolate(to, 255), to);
+        assert_eq!(from.interpolate(to, 51), VgaColor::new_rgb(51, 120, 204));
+    }
 }
@@ -0,0 +1,79 @@
+use crate::vga::psf::PsfFont;
+use crate::vga::VgaError;
+use noto_sans_mono_bitmap::{get_raster, get_raster_width, FontWeight, RasterHeight};
+
+/// The heights, in pixels, that the built-in font is rasterized at. noto-sans-mono-bitmap has no
+/// 14 px raster, so smaller fonts, such as the classic 8x14 VGA one, must be loaded as PSF.
+pub const NOTO_SIZES: [usize; 4] = [16, 20, 24, 32];
+
+/// The font the text buffer is drawn with.
+#[derive(Debug, Clone)]
+pub enum VgaFont {
+    /// The built-in Noto Sans Mono, anti-aliased and in every weight.
+    Noto(RasterHeight),
+    /// A bitmap font loaded from a PSF file.
+    Psf(PsfFont),
+}
+
+impl VgaFont {
+    /// The built-in font at `height` pixels, which must be one of [`NOTO_SIZES`].
+    pub fn noto(height: usize) -> Result<Self, VgaError> {
+        let size = match height {
+            16 => RasterHeight::Size16,
+            20 => RasterHeight::Size20,
+            24 => RasterHeight::Size24,
+            32 => RasterHeight::Size32,
+            _ => return Err(VgaError::UnsupportedFontSize(height)),
+        };
+        Ok(Self::Noto(size))
+    }
+
+    pub fn char_width(&self) -> usize {
+        match self {
+            // Every weight has the same width
+            Self::Noto(size) => get_raster_width(FontWeight::Regular, *size),
+            Self::Psf(font) => font.width(),
+        }
+    }
+
+    pub fn char_height(&self) -> usize {
+        match self {
+            Self::Noto(size) => size.val(),
+            Self::Psf(font) => font.height(),
+        }
+    }
+
+    /// Calls `pixel` with the position and intensity of every pixel of `char` in `weight`.
+    pub fn rasterize(
+        &self,
+        char: char,
+        weight: FontWeight,
+        mut pixel: impl FnMut(usize, usize, u8),
+    ) {
+        match self {
+            Self::Noto(size) => {
+                let raster = get_raster(char, weight, *size)
+                    .unwrap_or(
+                        get_raster(' ', weight, *size)
+                            .expect("Cannot get default raster for char while drawing to screen."),
+                    )
+                    .raster();
+                for (y, row) in raster.iter().enumerate() {
+                    for (x, intensity) in row.iter().enumerate() {
+                        pixel(x, y, *intensity);
+                    }
+                }
+            }
+            Self::Psf(font) => {
+                let bold = weight.val() == FontWeight::Bold.val();
+                font.rasterize(char, bold, pixel);
+            }
+        }
+    }
+}
+
+impl Default for VgaFont {
+    fn default() -> Self {
+        Self::Noto(RasterHeight::Size16)
+    }
+}
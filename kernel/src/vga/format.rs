@@ -0,0 +1,184 @@
+use crate::vga::color::VgaColor;
+use crate::vga::VgaError;
+use bootloader_api::info::PixelFormat;
+
+/// How the color of a pixel is stored in the framebuffer.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PixelEncoding {
+    /// Each color channel is a byte, at the given bit offset of the pixel.
+    Channels {
+        red_shift: u8,
+        green_shift: u8,
+        blue_shift: u8,
+    },
+    /// A single byte with the luminance of the color.
+    Grayscale,
+}
+
+/// The layout of the pixels of a framebuffer, to convert colors from and to their bytes.
+///
+/// Pixels are stored as little endian integers of `bytes_per_pixel` bytes, which may be padded.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct VgaPixelFormat {
+    encoding: PixelEncoding,
+    bytes_per_pixel: usize,
+}
+
+impl VgaPixelFormat {
+    pub fn new(format: PixelFormat, bytes_per_pixel: usize) -> Result<Self, VgaError> {
+        if ![1, 3, 4].contains(&bytes_per_pixel) {
+            return Err(VgaError::UnsupportedBytesPerPixel(bytes_per_pixel));
+        }
+        let encoding = match format {
+            PixelFormat::Rgb => PixelEncoding::Channels {
+                red_shift: 0,
+                green_shift: 8,
+                blue_shift: 16,
+            },
+            PixelFormat::Bgr => PixelEncoding::Channels {
+                red_shift: 16,
+                green_shift: 8,
+                blue_shift: 0,
+            },
+            PixelFormat::U8 => PixelEncoding::Grayscale,
+            PixelFormat::Unknown {
+                red_position,
+                green_position,
+                blue_position,
+            } => PixelEncoding::Channels {
+                red_shift: red_position,
+                green_shift: green_position,
+                blue_shift: blue_position,
+            },
+            _ => return Err(VgaError::UnsupportedPixelFormat(format)),
+        };
+        let bits = bytes_per_pixel as u32 * 8;
+        let fits = match encoding {
+            PixelEncoding::Channels {
+                red_shift,
+                green_shift,
+                blue_shift,
+            } => [red_shift, green_shift, blue_shift]
+                .iter()
+                .all(|&shift| shift as u32 + 8 <= bits),
+            PixelEncoding::Grayscale => true,
+        };
+        if !fits {
+            return Err(VgaError::UnsupportedPixelFormat(format));
+        }
+        Ok(Self {
+            encoding,
+            bytes_per_pixel,
+        })
+    }
+
+    pub fn encoding(&self) -> PixelEncoding {
+        self.encoding
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes_per_pixel
+    }
+
+    /// The integer a pixel of `color` is stored as.
+    pub fn encode(&self, color: VgaColor) -> u32 {
+        match self.encoding {
+            PixelEncoding::Channels {
+                red_shift,
+                green_shift,
+                blue_shift,
+            } => {
+                (color.red_val() as u32) << red_shift
+                    | (color.green_val() as u32) << green_shift
+                    | (color.blue_val() as u32) << blue_shift
+            }
+            PixelEncoding::Grayscale => luminance(color) as u32,
+        }
+    }
+
+    pub fn decode(&self, value: u32) -> VgaColor {
+        match self.encoding {
+            PixelEncoding::Channels {
+                red_shift,
+                green_shift,
+                blue_shift,
+            } => VgaColor::new_rgb(
+                (value >> red_shift) as u8,
+                (value >> green_shift) as u8,
+                (value >> blue_shift) as u8,
+            ),
+            PixelEncoding::Grayscale => {
+                let value = value as u8;
+                VgaColor::new_rgb(value, value, value)
+            }
+        }
+    }
+
+    /// Writes `color` to the first `bytes_per_pixel` bytes of `bytes`.
+    pub fn write(&self, bytes: &mut [u8], color: VgaColor) {
+        let value = self.encode(color).to_le_bytes();
+        bytes[..self.bytes_per_pixel].copy_from_slice(&value[..self.bytes_per_pixel]);
+    }
+
+    pub fn read(&self, bytes: &[u8]) -> VgaColor {
+        let mut value = [0; 4];
+        value[..self.bytes_per_pixel].copy_from_slice(&bytes[..self.bytes_per_pixel]);
+        self.decode(u32::from_le_bytes(value))
+    }
+}
+
+/// Perceived brightness of `color`, with the weights of ITU-R BT.601.
+fn luminance(color: VgaColor) -> u8 {
+    let red = color.red_val() as u32 * 299;
+    let green = color.green_val() as u32 * 587;
+    let blue = color.blue_val() as u32 * 114;
+    ((red + green + blue) / 1000) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vga::color::VgaColor;
+    use crate::vga::format::VgaPixelFormat;
+    use crate::vga::VgaError;
+    use bootloader_api::info::PixelFormat;
+
+    #[test]
+    fn pixel_format_test() {
+        let color = VgaColor::new_rgb(0x12, 0x34, 0x56);
+        let mut bytes = [0; 4];
+
+        let bgr = VgaPixelFormat::new(PixelFormat::Bgr, 3).unwrap();
+        bgr.write(&mut bytes, color);
+        assert_eq!(bytes, [0x56, 0x34, 0x12, 0]);
+        assert_eq!(bgr.read(&bytes), color);
+
+        let rgb = VgaPixelFormat::new(PixelFormat::Rgb, 4).unwrap();
+        rgb.write(&mut bytes, color);
+        assert_eq!(bytes, [0x12, 0x34, 0x56, 0]);
+        assert_eq!(rgb.read(&bytes), color);
+
+        let unknown = PixelFormat::Unknown {
+            red_position: 24,
+            green_position: 16,
+            blue_position: 8,
+        };
+        let rgbx = VgaPixelFormat::new(unknown, 4).unwrap();
+        rgbx.write(&mut bytes, color);
+        assert_eq!(bytes, [0, 0x56, 0x34, 0x12]);
+        assert_eq!(rgbx.read(&bytes), color);
+        assert_eq!(
+            VgaPixelFormat::new(unknown, 3),
+            Err(VgaError::UnsupportedPixelFormat(unknown))
+        );
+
+        let gray = VgaPixelFormat::new(PixelFormat::U8, 1).unwrap();
+        gray.write(&mut bytes, VgaColor::white());
+        assert_eq!(bytes[0], 255);
+        assert_eq!(gray.read(&[0x80]), VgaColor::new_rgb(0x80, 0x80, 0x80));
+
+        assert_eq!(
+            VgaPixelFormat::new(PixelFormat::Rgb, 2),
+            Err(VgaError::UnsupportedBytesPerPixel(2))
+        );
+    }
+}
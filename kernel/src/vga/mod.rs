@@ -1,20 +1,43 @@
+pub mod ansi;
+pub mod back_buffer;
+pub mod canvas;
 pub mod char;
 pub mod color;
+pub mod font;
+pub mod format;
 pub mod pixel;
+pub mod psf;
+pub mod rect;
+pub mod scrollback;
+pub mod terminal;
 
 use crate::utils::heap_array::HeapArray;
+use crate::vga::back_buffer::BackBuffer;
+use crate::vga::canvas::Canvas;
 use crate::vga::char::VgaChar;
 use crate::vga::color::VgaColor;
+use crate::vga::font::VgaFont;
+use crate::vga::format::VgaPixelFormat;
 use crate::vga::pixel::VgaPixel;
+use crate::vga::rect::Rect;
+use alloc::alloc::Global;
 use alloc::borrow::Cow;
-use alloc::vec::Vec;
 use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
 use char::VgaStyle;
-use noto_sans_mono_bitmap::{get_raster, get_raster_width, FontWeight, RasterHeight};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VgaError {
     HeapArrayError(crate::utils::heap_array::HeapArrayError),
+    UnsupportedPixelFormat(PixelFormat),
+    UnsupportedBytesPerPixel(usize),
+    /// The framebuffer cannot fit a single char.
+    ScreenTooSmall {
+        width: usize,
+        height: usize,
+    },
+    /// The built-in font is not rasterized at this height, see
+    /// [`NOTO_SIZES`](crate::vga::font::NOTO_SIZES).
+    UnsupportedFontSize(usize),
 }
 
 impl From<crate::utils::heap_array::HeapArrayError> for VgaError {
@@ -29,53 +52,98 @@ pub enum VgaMode {
     Pixels,
 }
 
-pub const TEXT_SCREEN_COLS: usize = 142;
-pub const TEXT_SCREEN_ROWS: usize = 45;
-pub const TEXT_BUFFER_SIZE: usize = TEXT_SCREEN_COLS * TEXT_SCREEN_ROWS * 15;
-
-pub const CHAR_WEIGHT: FontWeight = FontWeight::Regular;
-pub const CHAR_SIZE: RasterHeight = RasterHeight::Size16;
-pub const CHAR_WIDTH: usize = get_raster_width(CHAR_WEIGHT, CHAR_SIZE);
-pub const CHAR_HEIGHT: usize = CHAR_SIZE.val();
-
 pub struct VgaScreen<'a> {
     pub mode: VgaMode,
     framebuffer: &'a mut FrameBuffer,
+    format: VgaPixelFormat,
+    back_buffer: BackBuffer,
 
+    font: VgaFont,
+    /// The chars on the screen, row by row. Scrolling back through older rows is left to a
+    /// [`Terminal`](crate::vga::terminal::Terminal).
     text_buffer: HeapArray<VgaChar>,
-    pub text_offset: usize,
-    pixel_buffer: HeapArray<VgaPixel>,
+    text_cols: usize,
+    text_rows: usize,
+    /// `None` once released in text mode, until it is needed again.
+    pixel_buffer: Option<HeapArray<VgaPixel>>,
+    /// The index in the text buffer of the char drawn with its colors swapped.
+    cursor: Option<usize>,
+
+    /// The chars that are in the back buffer, or `None` if that cell must be drawn again.
+    drawn_chars: HeapArray<Option<VgaChar>>,
+    drawn_mode: VgaMode,
 }
 
 impl<'a> VgaScreen<'a> {
     pub fn new(framebuffer: &'a mut FrameBuffer) -> Result<Self, VgaError> {
+        Self::with_font(framebuffer, VgaFont::default())
+    }
+
+    pub fn with_font(framebuffer: &'a mut FrameBuffer, font: VgaFont) -> Result<Self, VgaError> {
         let info = framebuffer.info();
-        // TODO: Support more pixel formats
-        assert_eq!(
-            info.pixel_format,
-            PixelFormat::Bgr,
-            "{:?} format not supported.",
-            info.pixel_format
-        );
-        assert_eq!(
-            info.bytes_per_pixel, 3,
-            "{} bytes per pixel not supported.",
-            info.bytes_per_pixel
-        );
-        let pixel_buffer_length = info.byte_len;
-        let text_buffer = HeapArray::new(TEXT_BUFFER_SIZE)?;
-        let pixel_buffer = HeapArray::new(pixel_buffer_length / 3)?;
+        let format = VgaPixelFormat::new(info.pixel_format, info.bytes_per_pixel)?;
+        let (text_cols, text_rows) = Self::text_grid(&info, &font)?;
+        let text_buffer_size = text_cols * text_rows;
+        let text_buffer = HeapArray::new(text_buffer_size, Global)?;
+        let pixel_buffer = HeapArray::new(info.width * info.height, Global)?;
+        let back_buffer = BackBuffer::new(info.width, info.height, format)?;
+        let mut drawn_chars = HeapArray::new(text_buffer_size, Global)?;
+        drawn_chars.fill(None);
         let mut screen = Self {
             mode: VgaMode::Text,
             framebuffer,
+            format,
+            back_buffer,
+            font,
             text_buffer,
-            text_offset: 0,
-            pixel_buffer,
+            text_cols,
+            text_rows,
+            pixel_buffer: Some(pixel_buffer),
+            cursor: None,
+            drawn_chars,
+            drawn_mode: VgaMode::Text,
         };
         screen.clear_buffers();
         Ok(screen)
     }
 
+    pub fn font(&self) -> &VgaFont {
+        &self.font
+    }
+
+    /// Draws the text with `font` from now on. The text buffer is resized to the chars that fit
+    /// on the screen with it, which clears it.
+    pub fn set_font(&mut self, font: VgaFont) -> Result<(), VgaError> {
+        let (text_cols, text_rows) = Self::text_grid(&self.buffer_info(), &font)?;
+        let text_buffer_size = text_cols * text_rows;
+        if text_buffer_size != self.text_buffer.len() {
+            self.text_buffer = HeapArray::new(text_buffer_size, Global)?;
+            self.drawn_chars = HeapArray::new(text_buffer_size, Global)?;
+        }
+        self.font = font;
+        self.text_cols = text_cols;
+        self.text_rows = text_rows;
+        self.cursor = None;
+        self.text_buffer.fill(VgaChar::default());
+        self.drawn_chars.fill(None);
+        if self.mode == VgaMode::Text {
+            // Clear the margins the previous font may have drawn on
+            self.back_buffer.fill(VgaColor::black());
+            self.draw();
+        }
+        Ok(())
+    }
+
+    /// How many chars fit in a row of the screen.
+    pub fn text_cols(&self) -> usize {
+        self.text_cols
+    }
+
+    /// How many rows of chars fit on the screen.
+    pub fn text_rows(&self) -> usize {
+        self.text_rows
+    }
+
     pub fn text_buffer(&self) -> &HeapArray<VgaChar> {
         &self.text_buffer
     }
@@ -84,35 +152,96 @@ impl<'a> VgaScreen<'a> {
         &mut self.text_buffer
     }
 
-    pub fn pixel_buffer(&self) -> &HeapArray<VgaPixel> {
-        &self.pixel_buffer
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor
+            .map(|index| (index % self.text_cols, index / self.text_cols))
+    }
+
+    /// Shows a cursor over the char at `(col, row)` of the text buffer, or hides it.
+    pub fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+        self.cursor = cursor
+            .filter(|&(col, _)| col < self.text_cols)
+            .map(|(col, row)| row * self.text_cols + col)
+            .filter(|&index| index < self.text_buffer.len());
+    }
+
+    pub fn pixel_format(&self) -> VgaPixelFormat {
+        self.format
+    }
+
+    /// The pixel buffer, or `None` if it was released.
+    pub fn pixel_buffer(&self) -> Option<&HeapArray<VgaPixel>> {
+        self.pixel_buffer.as_ref()
+    }
+
+    /// The pixel buffer, allocated again all black if it was released.
+    pub fn pixel_buffer_mut(&mut self) -> Result<&mut HeapArray<VgaPixel>, VgaError> {
+        if self.pixel_buffer.is_none() {
+            let info = self.buffer_info();
+            let mut pixel_buffer = HeapArray::new(info.width * info.height, Global)?;
+            pixel_buffer.fill(VgaPixel(VgaColor::black()));
+            self.pixel_buffer = Some(pixel_buffer);
+        }
+        Ok(self.pixel_buffer.as_mut().unwrap())
+    }
+
+    /// Frees the pixel buffer while in text mode, such as when the allocator runs out of memory.
+    /// What was drawn on it is lost. Returns how many bytes were freed.
+    pub fn release_pixel_buffer(&mut self) -> usize {
+        if self.mode != VgaMode::Text {
+            return 0;
+        }
+        self.pixel_buffer
+            .take()
+            .map_or(0, |pixel_buffer| pixel_buffer.len() * size_of::<VgaPixel>())
     }
 
-    pub fn pixel_buffer_mut(&mut self) -> &mut HeapArray<VgaPixel> {
-        &mut self.pixel_buffer
+    /// Draws onto the pixel buffer, which is shown by [`VgaScreen::draw`] in [`VgaMode::Pixels`].
+    pub fn canvas(&mut self) -> Result<Canvas<'_>, VgaError> {
+        let width = self.back_buffer.width();
+        Ok(Canvas::new(self.pixel_buffer_mut()?, width))
     }
 
     pub fn clear_buffers(&mut self) {
         self.text_buffer.fill(VgaChar::default());
-        self.pixel_buffer.fill(VgaPixel(VgaColor::black()));
+        if let Some(pixel_buffer) = self.pixel_buffer.as_mut() {
+            pixel_buffer.fill(VgaPixel(VgaColor::black()));
+        }
     }
 
     pub fn clear_screen(&mut self) {
         self.clear_buffers();
-        self.buffer_mut().fill(0);
+        self.back_buffer.fill(VgaColor::black());
+        // A blank char is all black, so the cleared text buffer is already drawn
+        self.drawn_chars.fill(Some(VgaChar::default()));
+        self.drawn_mode = self.mode;
+        self.present();
     }
 
+    /// Draws the buffer of the current mode and shows it on the screen.
     pub fn draw(&mut self) {
+        if self.mode != self.drawn_mode {
+            self.drawn_chars.fill(None);
+            self.drawn_mode = self.mode;
+        }
         match self.mode {
             VgaMode::Text => self.draw_text_buffer(),
             VgaMode::Pixels => self.draw_pixels(),
         }
+        self.present();
+    }
+
+    /// Copies the parts of the back buffer that changed to the framebuffer.
+    pub fn present(&mut self) {
+        let stride = self.buffer_info().stride;
+        self.back_buffer
+            .present(self.framebuffer.buffer_mut(), stride);
     }
 
     pub fn print_text(&mut self, col: usize, row: usize, text: &str, style: VgaStyle) {
         // Update the text buffer with the new string
         let mut text = Cow::from(text);
-        let text_buffer_index = row * TEXT_SCREEN_COLS + col;
+        let text_buffer_index = row * self.text_cols + col;
 
         if text_buffer_index + text.len() > self.text_buffer.len() {
             text.to_mut()
@@ -124,99 +253,155 @@ impl<'a> VgaScreen<'a> {
         }
 
         // Draw the text onto the screen
-        let chars: Vec<VgaChar> = text
-            .as_ref()
-            .chars()
-            .map(|char| VgaChar::new(char, style))
-            .collect();
-        self.draw_chars(col, row, &chars);
+        if self.mode == VgaMode::Text {
+            self.draw();
+        }
     }
 
-    fn draw_text_buffer(&mut self) {
-        let chars = self.text_buffer.clone();
-        self.draw_chars(0, 0, &chars);
+    /// Moves the text up by `rows`, leaving blank rows at the bottom.
+    pub fn scroll_text(&mut self, rows: usize) {
+        self.scroll_text_area(self.text_rows, rows);
     }
 
-    fn draw_chars(&mut self, col: usize, row: usize, chars: &[VgaChar]) {
-        let mut curr_col = col;
-        for char in chars {
-            self.draw_char(
-                char,
-                (curr_col * CHAR_WIDTH) as isize,
-                (row * CHAR_HEIGHT) as isize - self.text_offset as isize,
-            );
-            curr_col += 1;
+    /// Moves the first `area_rows` rows of text up by `rows`, leaving blank rows at the bottom of
+    /// them. The rows below them stay in place.
+    pub fn scroll_text_area(&mut self, area_rows: usize, rows: usize) {
+        let area_rows = area_rows.min(self.text_rows);
+        let rows = rows.min(area_rows);
+        let len = area_rows * self.text_cols;
+        let shift = rows * self.text_cols;
+        self.text_buffer.copy_within(shift..len, 0);
+        self.text_buffer[len - shift..len].fill(VgaChar::default());
+
+        // Move the pixels that are already drawn too, so that only the new rows are rasterized
+        if self.mode == VgaMode::Text && self.drawn_mode == VgaMode::Text {
+            self.drawn_chars.copy_within(shift..len, 0);
+            self.drawn_chars[len - shift..len].fill(None);
+            let char_height = self.font.char_height();
+            self.back_buffer
+                .move_rows(rows * char_height, 0, (area_rows - rows) * char_height);
+        } else {
+            self.drawn_chars.fill(None);
         }
     }
 
-    fn draw_char(&mut self, char: &VgaChar, x: isize, y: isize) {
-        let raster = get_raster(char.char, CHAR_WEIGHT, CHAR_SIZE)
-            .unwrap_or(
-                get_raster(' ', CHAR_WEIGHT, CHAR_SIZE)
-                    .expect("Cannot get default raster for char while drawing to screen."),
-            )
-            .raster();
-
-        for (i, row) in raster.iter().enumerate() {
-            for (j, lightness) in row.iter().enumerate() {
-                if (x + j as isize) < 0 || (y + i as isize) < 0 {
-                    continue;
-                }
-
-                // TODO: Actually display the foreground and background colors set by VgaChar
-                self.buffer_set(
-                    (x + j as isize) as usize,
-                    (y + i as isize) as usize,
-                    VgaPixel(VgaColor::new_rgb(*lightness, *lightness, *lightness)),
-                )
-            }
+    fn text_grid(info: &FrameBufferInfo, font: &VgaFont) -> Result<(usize, usize), VgaError> {
+        let text_cols = info.width / font.char_width();
+        let text_rows = info.height / font.char_height();
+        if text_cols == 0 || text_rows == 0 {
+            return Err(VgaError::ScreenTooSmall {
+                width: info.width,
+                height: info.height,
+            });
         }
+        Ok((text_cols, text_rows))
     }
 
-    fn draw_pixels(&mut self) {
-        // WTF is this???
-        for (i, pixel) in self.pixel_buffer.clone().iter().enumerate() {
-            self.buffer_set(
-                i % self.buffer_info().width,
-                i / self.buffer_info().width,
-                *pixel,
-            )
+    /// Rasterizes the chars that changed since they were last drawn.
+    fn draw_text_buffer(&mut self) {
+        let char_width = self.font.char_width();
+        let char_height = self.font.char_height();
+        for i in 0..self.text_buffer.len() {
+            let mut char = self.text_buffer[i];
+            if self.cursor == Some(i) {
+                char.style = char.style.inverted();
+            }
+            if self.drawn_chars[i] == Some(char) {
+                continue;
+            }
+            self.draw_char(
+                &char,
+                (i % self.text_cols) * char_width,
+                (i / self.text_cols) * char_height,
+            );
+            self.drawn_chars[i] = Some(char);
         }
     }
 
-    fn buffer(&self) -> &[u8] {
-        self.framebuffer.buffer()
+    fn draw_char(&mut self, char: &VgaChar, x: usize, y: usize) {
+        let style = char.style;
+        let back_buffer = &mut self.back_buffer;
+        self.font
+            .rasterize(char.char, style.weight, |j, i, intensity| {
+                back_buffer.set(
+                    x + j,
+                    y + i,
+                    style.background.interpolate(style.foreground, intensity),
+                )
+            });
+        self.back_buffer.mark_dirty(Rect::new(
+            x,
+            y,
+            self.font.char_width(),
+            self.font.char_height(),
+        ));
     }
 
-    fn buffer_mut(&mut self) -> &mut [u8] {
-        self.framebuffer.buffer_mut()
+    fn draw_pixels(&mut self) {
+        let Some(pixel_buffer) = self.pixel_buffer.as_ref() else {
+            // A released pixel buffer comes back all black
+            let bounds = self.back_buffer.bounds();
+            self.back_buffer.fill_rect(bounds, VgaColor::black());
+            return;
+        };
+        let width = self.back_buffer.width();
+        for (y, row) in pixel_buffer.chunks(width).enumerate() {
+            self.back_buffer.update_row(y, row);
+        }
     }
 
     fn buffer_info(&self) -> FrameBufferInfo {
         self.framebuffer.info()
     }
+}
 
-    fn buffer_pos(&self, x: usize, y: usize) -> usize {
-        (y * self.buffer_info().stride + x) * 3
-    }
-
-    fn buffer_get(&self, x: usize, y: usize) -> VgaPixel {
-        let pos = self.buffer_pos(x, y);
-        let buffer = self.buffer();
-        let r = buffer[pos + 2];
-        let g = buffer[pos + 1];
-        let b = buffer[pos];
-        VgaPixel(VgaColor::new_rgb(r, g, b))
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::vga::color::VgaColor;
+    use crate::vga::pixel::VgaPixel;
+    use crate::vga::{VgaMode, VgaScreen};
+    use alloc::vec;
+    use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
+
+    /// A grayscale framebuffer of `width` by `height` pixels drawing to `bytes`.
+    pub(crate) fn test_framebuffer(bytes: &mut [u8], width: usize, height: usize) -> FrameBuffer {
+        let info = FrameBufferInfo {
+            byte_len: bytes.len(),
+            width,
+            height,
+            pixel_format: PixelFormat::U8,
+            bytes_per_pixel: 1,
+            stride: width,
+        };
+        unsafe { FrameBuffer::new(bytes.as_mut_ptr() as u64, info) }
     }
 
-    fn buffer_set(&mut self, x: usize, y: usize, pixel: VgaPixel) {
-        if x >= self.buffer_info().width || y >= self.buffer_info().height {
-            return;
-        }
-        let pos = self.buffer_pos(x, y);
-        let buffer = self.buffer_mut();
-        buffer[pos + 2] = pixel.0.red_val();
-        buffer[pos + 1] = pixel.0.green_val();
-        buffer[pos] = pixel.0.blue_val();
+    #[test]
+    fn release_pixel_buffer_test() {
+        let (width, height) = (64, 32);
+        let mut bytes = vec![0; width * height];
+        let mut framebuffer = test_framebuffer(&mut bytes, width, height);
+        let mut screen = VgaScreen::new(&mut framebuffer).unwrap();
+        screen.mode = VgaMode::Pixels;
+        screen.canvas().unwrap().fill(VgaColor::white());
+        screen.draw();
+        assert!(bytes.iter().all(|&pixel| pixel == 255));
+        // It is only released while it is not shown
+        assert_eq!(screen.release_pixel_buffer(), 0);
+
+        screen.mode = VgaMode::Text;
+        let size = width * height * size_of::<VgaPixel>();
+        assert_eq!(screen.release_pixel_buffer(), size);
+        assert!(screen.pixel_buffer().is_none());
+        assert_eq!(screen.release_pixel_buffer(), 0);
+
+        // It comes back all black
+        screen.mode = VgaMode::Pixels;
+        screen.draw();
+        assert!(bytes.iter().all(|&pixel| pixel == 0));
+        let pixel_buffer = screen.pixel_buffer_mut().unwrap();
+        assert!(pixel_buffer
+            .iter()
+            .all(|pixel| pixel.0 == VgaColor::black()));
     }
 }
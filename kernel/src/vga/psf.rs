@@ -0,0 +1,276 @@
+use alloc::vec::Vec;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_HAS_SEQ: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQ: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQ: u8 = 0xfe;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PsfError {
+    /// The data starts with neither the PSF1 nor the PSF2 magic number.
+    BadMagic,
+    /// The data ends before the glyphs or the Unicode table in the header.
+    Truncated,
+    /// The header describes glyphs that cannot be drawn, such as empty ones.
+    InvalidHeader,
+}
+
+/// A bitmap font in the PC Screen Font format of the Linux console, version 1 or 2.
+#[derive(Debug, Clone)]
+pub struct PsfFont {
+    width: usize,
+    height: usize,
+    glyph_count: usize,
+    /// Every glyph, one after the other, with each row padded to a whole byte.
+    glyphs: Vec<u8>,
+    /// The glyph of each char, sorted by char. Chars are glyph indices if the font has no table.
+    unicode: Vec<(char, usize)>,
+}
+
+impl PsfFont {
+    /// Loads a font from the contents of a `.psf` file, such as one embedded with
+    /// `include_bytes!` or read from a ramdisk.
+    pub fn parse(data: &[u8]) -> Result<Self, PsfError> {
+        if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)
+        } else if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)
+        } else {
+            Err(PsfError::BadMagic)
+        }
+    }
+
+    fn parse_psf1(data: &[u8]) -> Result<Self, PsfError> {
+        let header = data.get(..PSF1_HEADER_SIZE).ok_or(PsfError::Truncated)?;
+        let mode = header[2];
+        let height = header[3] as usize;
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let glyphs_end = PSF1_HEADER_SIZE + glyph_count * height;
+        let glyphs = data
+            .get(PSF1_HEADER_SIZE..glyphs_end)
+            .ok_or(PsfError::Truncated)?;
+
+        let mut unicode = Vec::new();
+        if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQ) != 0 {
+            let mut entries = data[glyphs_end..]
+                .chunks_exact(2)
+                .map(|entry| u16::from_le_bytes([entry[0], entry[1]]));
+            for glyph in 0..glyph_count {
+                let mut in_sequence = false;
+                loop {
+                    match entries.next().ok_or(PsfError::Truncated)? {
+                        PSF1_SEPARATOR => break,
+                        PSF1_START_SEQ => in_sequence = true,
+                        // Only single chars are supported, not combining sequences
+                        code if !in_sequence => {
+                            if let Some(char) = char::from_u32(code as u32) {
+                                unicode.push((char, glyph));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Self::new(8, height, glyph_count, glyphs, unicode)
+    }
+
+    fn parse_psf2(data: &[u8]) -> Result<Self, PsfError> {
+        let header = data.get(..PSF2_HEADER_SIZE).ok_or(PsfError::Truncated)?;
+        let field = |index: usize| {
+            let bytes = &header[index * 4..index * 4 + 4];
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+        };
+        let header_size = field(2);
+        let flags = field(3) as u32;
+        let glyph_count = field(4);
+        let glyph_size = field(5);
+        let height = field(6);
+        let width = field(7);
+        if header_size < PSF2_HEADER_SIZE || glyph_size != width.div_ceil(8) * height {
+            return Err(PsfError::InvalidHeader);
+        }
+        let glyphs_end = glyph_count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(PsfError::InvalidHeader)?;
+        let glyphs = data
+            .get(header_size..glyphs_end)
+            .ok_or(PsfError::Truncated)?;
+
+        let mut unicode = Vec::new();
+        if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            let mut table = data[glyphs_end..].split(|&byte| byte == PSF2_SEPARATOR);
+            for glyph in 0..glyph_count {
+                let entry = table.next().ok_or(PsfError::Truncated)?;
+                // Only single chars are supported, not combining sequences
+                let chars = entry.split(|&byte| byte == PSF2_START_SEQ).next().unwrap();
+                if let Ok(chars) = core::str::from_utf8(chars) {
+                    unicode.extend(chars.chars().map(|char| (char, glyph)));
+                }
+            }
+        }
+        Self::new(width, height, glyph_count, glyphs, unicode)
+    }
+
+    fn new(
+        width: usize,
+        height: usize,
+        glyph_count: usize,
+        glyphs: &[u8],
+        mut unicode: Vec<(char, usize)>,
+    ) -> Result<Self, PsfError> {
+        if width == 0 || height == 0 || glyph_count == 0 {
+            return Err(PsfError::InvalidHeader);
+        }
+        // Keep the first glyph of chars that are in the table more than once
+        unicode.sort_by_key(|&(char, _)| char);
+        unicode.dedup_by_key(|&mut (char, _)| char);
+        Ok(Self {
+            width,
+            height,
+            glyph_count,
+            glyphs: glyphs.to_vec(),
+            unicode,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyph_count
+    }
+
+    /// The index of the glyph that draws `char`, if the font has one.
+    pub fn glyph_index(&self, char: char) -> Option<usize> {
+        if self.unicode.is_empty() {
+            return Some(char as usize).filter(|&index| index < self.glyph_count);
+        }
+        self.unicode
+            .binary_search_by_key(&char, |&(char, _)| char)
+            .ok()
+            .map(|position| self.unicode[position].1)
+    }
+
+    /// The rows of the glyph at `index`, each one `width.div_ceil(8)` bytes with the leftmost pixel
+    /// in the highest bit.
+    pub fn glyph(&self, index: usize) -> &[u8] {
+        let size = self.bytes_per_row() * self.height;
+        &self.glyphs[index * size..(index + 1) * size]
+    }
+
+    /// Calls `pixel` with the position and intensity of every pixel of `char`, making its strokes
+    /// a pixel wider if `bold`. Chars missing from the font are drawn blank.
+    pub fn rasterize(&self, char: char, bold: bool, mut pixel: impl FnMut(usize, usize, u8)) {
+        let glyph = self.glyph_index(char).map(|index| self.glyph(index));
+        let bytes_per_row = self.bytes_per_row();
+        for y in 0..self.height {
+            let row = glyph.map(|glyph| &glyph[y * bytes_per_row..(y + 1) * bytes_per_row]);
+            let is_set = |x: usize| row.is_some_and(|row| row[x / 8] & (0x80 >> (x % 8)) != 0);
+            for x in 0..self.width {
+                let set = is_set(x) || (bold && x > 0 && is_set(x - 1));
+                pixel(x, y, if set { 255 } else { 0 });
+            }
+        }
+    }
+
+    fn bytes_per_row(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vga::psf::{PsfError, PsfFont};
+    use alloc::vec;
+
+    #[test]
+    fn psf2_test() {
+        // Two 10x2 glyphs, the second one drawing `A` and `Ä`
+        let mut data = vec![0x72, 0xb5, 0x4a, 0x86];
+        for field in [0u32, 32, 1, 2, 4, 2, 10] {
+            data.extend(field.to_le_bytes());
+        }
+        data.extend([0, 0, 0, 0, 0b1000_0000, 0b0100_0000, 0b0000_0001, 0]);
+        data.extend(b"?\xff");
+        data.extend("AÄ".as_bytes());
+        data.push(0xfe);
+        data.extend("A\u{308}".as_bytes());
+        data.push(0xff);
+
+        let font = PsfFont::parse(&data).unwrap();
+        assert_eq!(
+            (font.width(), font.height(), font.glyph_count()),
+            (10, 2, 2)
+        );
+        assert_eq!(font.glyph_index('?'), Some(0));
+        assert_eq!(font.glyph_index('A'), Some(1));
+        assert_eq!(font.glyph_index('Ä'), Some(1));
+        assert_eq!(font.glyph_index('B'), None);
+
+        let mut pixels = [[0; 10]; 2];
+        font.rasterize('A', false, |x, y, intensity| pixels[y][x] = intensity);
+        assert_eq!(pixels[0][0], 255);
+        assert_eq!(pixels[0][9], 255);
+        assert_eq!(pixels[1][7], 255);
+        assert_eq!(
+            pixels.iter().flatten().filter(|&&pixel| pixel != 0).count(),
+            3
+        );
+        font.rasterize('A', true, |x, y, intensity| pixels[y][x] = intensity);
+        assert_eq!(pixels[0][1], 255);
+        assert_eq!(
+            pixels.iter().flatten().filter(|&&pixel| pixel != 0).count(),
+            5
+        );
+
+        assert_eq!(
+            PsfFont::parse(&data[..40]).unwrap_err(),
+            PsfError::Truncated
+        );
+        assert_eq!(PsfFont::parse(b"font").unwrap_err(), PsfError::BadMagic);
+    }
+
+    #[test]
+    fn psf1_test() {
+        // 256 glyphs of 8x1 with a table, where glyph 1 draws `a` and `b`
+        let mut data = vec![0x36, 0x04, 0x02, 1];
+        data.extend(0..=255u8);
+        for glyph in 0..256u16 {
+            match glyph {
+                1 => data.extend([b'a', 0, b'b', 0, 0xfe, 0xff, b'c', 0, 0xff, 0xff]),
+                _ => data.extend([0xff, 0xff]),
+            }
+        }
+        let font = PsfFont::parse(&data).unwrap();
+        assert_eq!(
+            (font.width(), font.height(), font.glyph_count()),
+            (8, 1, 256)
+        );
+        assert_eq!(font.glyph_index('a'), Some(1));
+        assert_eq!(font.glyph_index('b'), Some(1));
+        assert_eq!(font.glyph_index('c'), None);
+        assert_eq!(font.glyph(1), [1]);
+
+        // Without a table, chars are glyph indices
+        data[2] = 0;
+        let font = PsfFont::parse(&data[..4 + 256]).unwrap();
+        assert_eq!(font.glyph_index('A'), Some(65));
+        assert_eq!(font.glyph_index('Ā'), None);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::utils::heap_array::{HeapArray, HeapArrayError};
+use crate::vga::char::VgaChar;
+use alloc::alloc::Global;
+use core::ops::Range;
+
+/// A ring buffer of rows of text, which drops the oldest row when a new one does not fit.
+pub struct Scrollback {
+    chars: HeapArray<VgaChar>,
+    cols: usize,
+    capacity: usize,
+    /// The position in the ring of the oldest row.
+    first: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    /// Creates a ring of `capacity` rows of `cols` chars, which must not be 0.
+    pub fn new(capacity: usize, cols: usize) -> Result<Self, HeapArrayError> {
+        assert!(capacity > 0, "Cannot create a scrollback without rows.");
+        let mut chars = HeapArray::new(capacity * cols, Global)?;
+        chars.fill(VgaChar::default());
+        Ok(Self {
+            chars,
+            cols,
+            capacity,
+            first: 0,
+            len: 0,
+        })
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The row at `index`, counting from the oldest one.
+    pub fn row(&self, index: usize) -> &[VgaChar] {
+        let range = self.row_range(index);
+        &self.chars[range]
+    }
+
+    pub fn row_mut(&mut self, index: usize) -> &mut [VgaChar] {
+        let range = self.row_range(index);
+        &mut self.chars[range]
+    }
+
+    /// Adds a row filled with `fill` after the newest one, dropping the oldest row if full.
+    pub fn push_row(&mut self, fill: VgaChar) {
+        if self.len < self.capacity {
+            self.len += 1;
+        } else {
+            self.first = (self.first + 1) % self.capacity;
+        }
+        self.row_mut(self.len - 1).fill(fill);
+    }
+
+    pub fn clear(&mut self) {
+        self.first = 0;
+        self.len = 0;
+    }
+
+    fn row_range(&self, index: usize) -> Range<usize> {
+        assert!(
+            index < self.len,
+            "Row {index} is out of the scrollback of {} rows.",
+            self.len
+        );
+        let start = (self.first + index) % self.capacity * self.cols;
+        start..start + self.cols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vga::char::{VgaChar, VgaStyle};
+    use crate::vga::scrollback::Scrollback;
+
+    #[test]
+    fn ring_test() {
+        let mut scrollback = Scrollback::new(3, 4).unwrap();
+        assert!(scrollback.is_empty());
+        for char in ['a', 'b', 'c', 'd', 'e'] {
+            scrollback.push_row(VgaChar::new(char, VgaStyle::default()));
+        }
+        assert_eq!(scrollback.len(), 3);
+        let firsts = (0..3).map(|row| scrollback.row(row)[0].char);
+        assert!(firsts.eq(['c', 'd', 'e']));
+
+        scrollback.row_mut(0)[1].char = 'x';
+        assert_eq!(scrollback.row(0)[1].char, 'x');
+        assert_eq!(scrollback.row(1)[1].char, 'd');
+        scrollback.clear();
+        assert!(scrollback.is_empty());
+    }
+}
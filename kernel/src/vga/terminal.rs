@@ -0,0 +1,653 @@
+use crate::vga::ansi::{apply_sgr, AnsiAction, AnsiParser, CsiSequence};
+use crate::vga::char::{VgaChar, VgaStyle};
+use crate::vga::font::VgaFont;
+use crate::vga::scrollback::Scrollback;
+use crate::vga::{VgaError, VgaMode, VgaScreen};
+use core::fmt;
+use core::fmt::Write;
+use core::ops::Range;
+
+/// Columns between tab stops.
+pub const TAB_WIDTH: usize = 8;
+/// How many screens of rows [`Terminal::new`] keeps above the screen.
+pub const SCROLLBACK_SCREENS: usize = 14;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CursorStyle {
+    Hidden,
+    Solid,
+    /// Shown and hidden on every call to [`Terminal::blink`].
+    Blinking,
+}
+
+/// Writes text to the screen like a teletype, wrapping long lines and scrolling up when it reaches
+/// the bottom. Runs the VT100/ANSI escape sequences in the text, as a serial console would.
+///
+/// The rows that scroll off the top are kept in a scrollback, which can be shown by moving the
+/// view up with [`Terminal::page_up`] and the like.
+pub struct Terminal<'a> {
+    screen: VgaScreen<'a>,
+    cols: usize,
+    rows: usize,
+    /// Rows at the bottom of the screen that are left out of the terminal.
+    reserved_rows: usize,
+    /// The scrollback, followed by the rows of the screen.
+    lines: Scrollback,
+    /// How many rows the view is above the bottom.
+    view_offset: usize,
+    /// Rows scrolled since the last draw, to move what is already on the screen.
+    scrolled: usize,
+    col: usize,
+    row: usize,
+    style: VgaStyle,
+    cursor_style: CursorStyle,
+    cursor_shown: bool,
+    parser: AnsiParser,
+    /// The cursor and style stored by `ESC 7` or `ESC [ s`.
+    saved: (usize, usize, VgaStyle),
+}
+
+impl<'a> Terminal<'a> {
+    /// Creates a terminal that keeps [`SCROLLBACK_SCREENS`] screens of rows above the screen.
+    pub fn new(screen: VgaScreen<'a>) -> Result<Self, VgaError> {
+        let scrollback_lines = screen.text_rows() * SCROLLBACK_SCREENS;
+        Self::with_scrollback(screen, scrollback_lines)
+    }
+
+    /// Creates a terminal that keeps up to `scrollback_lines` rows above the screen.
+    pub fn with_scrollback(
+        mut screen: VgaScreen<'a>,
+        scrollback_lines: usize,
+    ) -> Result<Self, VgaError> {
+        let cols = screen.text_cols();
+        let rows = screen.text_rows();
+        let mut lines = Scrollback::new(scrollback_lines + rows, cols)?;
+        for _ in 0..rows {
+            lines.push_row(VgaChar::default());
+        }
+        screen.mode = VgaMode::Text;
+        screen.clear_screen();
+        let mut terminal = Self {
+            screen,
+            cols,
+            rows,
+            reserved_rows: 0,
+            lines,
+            view_offset: 0,
+            scrolled: 0,
+            col: 0,
+            row: 0,
+            style: VgaStyle::default(),
+            cursor_style: CursorStyle::Solid,
+            cursor_shown: true,
+            parser: AnsiParser::new(),
+            saved: (0, 0, VgaStyle::default()),
+        };
+        terminal.draw();
+        Ok(terminal)
+    }
+
+    pub fn screen(&self) -> &VgaScreen<'a> {
+        &self.screen
+    }
+
+    pub fn screen_mut(&mut self) -> &mut VgaScreen<'a> {
+        &mut self.screen
+    }
+
+    pub fn into_screen(self) -> VgaScreen<'a> {
+        self.screen
+    }
+
+    /// How many chars fit in a row of the terminal.
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// How many rows of the screen the terminal uses, from the top. The reserved rows are below
+    /// them.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.col, self.row)
+    }
+
+    pub fn set_cursor(&mut self, col: usize, row: usize) {
+        self.col = col.min(self.cols - 1);
+        self.row = row.min(self.rows - 1);
+        self.draw();
+    }
+
+    pub fn style(&self) -> VgaStyle {
+        self.style
+    }
+
+    /// Sets the style of the text written from now on.
+    pub fn set_style(&mut self, style: VgaStyle) {
+        self.style = style;
+    }
+
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.cursor_style
+    }
+
+    pub fn set_cursor_style(&mut self, cursor_style: CursorStyle) {
+        self.cursor_style = cursor_style;
+        self.cursor_shown = true;
+        self.draw();
+    }
+
+    /// Toggles a blinking cursor, to be called periodically, such as from a timer.
+    pub fn blink(&mut self) {
+        if self.cursor_style == CursorStyle::Blinking {
+            self.cursor_shown = !self.cursor_shown;
+            self.draw();
+        }
+    }
+
+    /// Clears the screen, keeping the scrollback and the reserved rows.
+    pub fn clear(&mut self) {
+        for row in 0..self.rows {
+            self.screen_row_mut(row).fill(VgaChar::default());
+        }
+        self.view_offset = 0;
+        self.scrolled = 0;
+        self.col = 0;
+        self.row = 0;
+        self.draw();
+    }
+
+    /// Draws the text with `font` from now on. This clears the screen and the scrollback, as their
+    /// rows no longer fit.
+    pub fn set_font(&mut self, font: VgaFont) -> Result<(), VgaError> {
+        self.screen.set_font(font)?;
+        self.resize()
+    }
+
+    /// Leaves the last `rows` rows of the screen out of the terminal, such as for a status line
+    /// that stays in place while the text scrolls. They can be written with
+    /// [`VgaScreen::print_text`] from row [`Terminal::rows`] on. At least one row is left to the
+    /// terminal.
+    ///
+    /// Like [`Terminal::set_font`], this clears the screen and the scrollback.
+    pub fn reserve_rows(&mut self, rows: usize) -> Result<(), VgaError> {
+        self.reserved_rows = rows;
+        self.resize()
+    }
+
+    /// Fits the terminal to the text grid of the screen, without its reserved rows.
+    fn resize(&mut self) -> Result<(), VgaError> {
+        let scrollback_lines = self.lines.capacity() - self.rows;
+        let text_rows = self.screen.text_rows();
+        self.cols = self.screen.text_cols();
+        self.rows = text_rows - self.reserved_rows.min(text_rows - 1);
+        self.lines = Scrollback::new(scrollback_lines + self.rows, self.cols)?;
+        for _ in 0..self.rows {
+            self.lines.push_row(VgaChar::default());
+        }
+        self.saved = (0, 0, self.style);
+        self.screen.clear_screen();
+        self.clear();
+        Ok(())
+    }
+
+    /// Rows of the scrollback, above the screen.
+    pub fn scrollback_len(&self) -> usize {
+        self.lines.len() - self.rows
+    }
+
+    pub fn view_offset(&self) -> usize {
+        self.view_offset
+    }
+
+    /// Moves the view `rows` up into the scrollback, such as for Shift+Up.
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.view_offset = (self.view_offset + rows).min(self.scrollback_len());
+        self.draw();
+    }
+
+    /// Moves the view `rows` down towards the screen.
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.view_offset = self.view_offset.saturating_sub(rows);
+        self.draw();
+    }
+
+    /// Moves the view up a screen, for Shift+PageUp.
+    pub fn page_up(&mut self) {
+        self.scroll_up((self.rows - 1).max(1));
+    }
+
+    /// Moves the view down a screen, for Shift+PageDown.
+    pub fn page_down(&mut self) {
+        self.scroll_down((self.rows - 1).max(1));
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_down(self.view_offset);
+    }
+
+    /// Writes `char` to the text buffer without drawing it, running the escape sequences it
+    /// completes.
+    pub fn put_char(&mut self, char: char) {
+        match self.parser.advance(char) {
+            Some(AnsiAction::Print(char)) => self.print(char),
+            Some(AnsiAction::Csi(sequence)) => self.run_sequence(&sequence),
+            Some(AnsiAction::SaveCursor) => self.saved = (self.col, self.row, self.style),
+            Some(AnsiAction::RestoreCursor) => (self.col, self.row, self.style) = self.saved,
+            Some(AnsiAction::Reset) => {
+                self.style = VgaStyle::default();
+                self.cursor_style = CursorStyle::Solid;
+                self.saved = (0, 0, VgaStyle::default());
+                self.lines.clear();
+                for _ in 0..self.rows {
+                    self.lines.push_row(VgaChar::default());
+                }
+                self.clear();
+            }
+            None => {}
+        }
+    }
+
+    /// Interprets `\n`, `\r`, `\t` and backspace, and ignores other control chars.
+    fn print(&mut self, char: char) {
+        match char {
+            '\n' => self.new_line(),
+            '\r' => self.col = 0,
+            '\t' => {
+                let stop = (self.col / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.col < stop.min(self.cols) {
+                    self.put_printable(' ');
+                }
+            }
+            '\x08' => self.backspace(),
+            char if char.is_control() => {}
+            char => self.put_printable(char),
+        }
+    }
+
+    /// Shows the rows in view and the cursor on the screen.
+    pub fn draw(&mut self) {
+        if self.view_offset == 0 && self.scrolled > 0 {
+            self.screen.scroll_text_area(self.rows, self.scrolled);
+        }
+        self.scrolled = 0;
+
+        let first = self.lines.len() - self.rows - self.view_offset;
+        for row in 0..self.rows {
+            let start = row * self.cols;
+            self.screen.text_buffer_mut()[start..start + self.cols]
+                .copy_from_slice(self.lines.row(first + row));
+        }
+
+        let shown = match self.cursor_style {
+            CursorStyle::Hidden => false,
+            CursorStyle::Solid => true,
+            CursorStyle::Blinking => self.cursor_shown,
+        };
+        // After filling a line, the cursor stays over its last char until the next one wraps
+        let cursor = (self.col.min(self.cols - 1), self.row);
+        if self.view_offset > 0 {
+            self.draw_indicator();
+            self.screen.set_cursor(None);
+        } else {
+            self.screen.set_cursor(shown.then_some(cursor));
+        }
+        self.screen.draw();
+    }
+
+    /// Shows how far up the view is at the top right corner of the screen.
+    fn draw_indicator(&mut self) {
+        // The logger draws while it is locked, even from the OOM and panic handlers, so this must
+        // not allocate
+        let (view_offset, scrollback_len) = (self.view_offset, self.scrollback_len());
+        let write =
+            |writer: &mut RowWriter| write!(writer, " Scrollback {view_offset}/{scrollback_len} ");
+        let style = VgaStyle::default().inverted();
+        let mut measure = RowWriter {
+            chars: &mut [],
+            col: 0,
+            style,
+        };
+        let _ = write(&mut measure);
+        let mut writer = RowWriter {
+            chars: &mut self.screen.text_buffer_mut()[..self.cols],
+            col: self.cols.saturating_sub(measure.col),
+            style,
+        };
+        let _ = write(&mut writer);
+    }
+
+    fn run_sequence(&mut self, sequence: &CsiSequence) {
+        let count = sequence.param(0, 1) as usize;
+        let col = self.col.min(self.cols - 1);
+        let cursor = self.row * self.cols + col;
+        let line = self.row * self.cols;
+        match (sequence.private, sequence.action) {
+            (false, 'A') => self.row = self.row.saturating_sub(count),
+            (false, 'B') => self.row = (self.row + count).min(self.rows - 1),
+            (false, 'C') => self.col = (col + count).min(self.cols - 1),
+            (false, 'D') => self.col = col.saturating_sub(count),
+            (false, 'G') => self.col = (count - 1).min(self.cols - 1),
+            (false, 'd') => self.row = (count - 1).min(self.rows - 1),
+            (false, 'H' | 'f') => {
+                self.row = (count - 1).min(self.rows - 1);
+                self.col = (sequence.param(1, 1) as usize - 1).min(self.cols - 1);
+            }
+            (false, 'J') => match sequence.param(0, 0) {
+                0 => self.erase(cursor..self.cols * self.rows),
+                1 => self.erase(0..cursor + 1),
+                _ => self.erase(0..self.cols * self.rows),
+            },
+            (false, 'K') => match sequence.param(0, 0) {
+                0 => self.erase(cursor..line + self.cols),
+                1 => self.erase(line..cursor + 1),
+                _ => self.erase(line..line + self.cols),
+            },
+            (false, 'm') => self.style = apply_sgr(self.style, sequence.params()),
+            (false, 's') => self.saved = (self.col, self.row, self.style),
+            (false, 'u') => (self.col, self.row, self.style) = self.saved,
+            (true, 'h') if sequence.params() == [25] => {
+                if self.cursor_style == CursorStyle::Hidden {
+                    self.cursor_style = CursorStyle::Solid;
+                }
+            }
+            (true, 'l') if sequence.params() == [25] => self.cursor_style = CursorStyle::Hidden,
+            _ => {}
+        }
+    }
+
+    /// Clears the chars at `range` of the screen, keeping the background color of the style.
+    fn erase(&mut self, range: Range<usize>) {
+        let blank = VgaChar::new(' ', self.style);
+        let cols = self.cols;
+        for index in range {
+            self.screen_row_mut(index / cols)[index % cols] = blank;
+        }
+    }
+
+    fn put_printable(&mut self, char: char) {
+        if self.col >= self.cols {
+            self.new_line();
+        }
+        self.set_char(self.col, self.row, char);
+        self.col += 1;
+    }
+
+    /// Moves back and erases the previous char, going back to the end of the previous line when
+    /// at the start of one.
+    fn backspace(&mut self) {
+        if self.col > 0 {
+            self.col = self.col.min(self.cols) - 1;
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.col = self.cols - 1;
+        } else {
+            return;
+        }
+        self.set_char(self.col, self.row, ' ');
+    }
+
+    fn new_line(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.lines.push_row(VgaChar::default());
+            if self.view_offset == 0 {
+                self.scrolled += 1;
+            } else {
+                // Keep showing the same rows, unless they were dropped
+                self.view_offset = (self.view_offset + 1).min(self.scrollback_len());
+            }
+        }
+    }
+
+    fn set_char(&mut self, col: usize, row: usize, char: char) {
+        self.screen_row_mut(row)[col] = VgaChar::new(char, self.style);
+    }
+
+    fn screen_row_mut(&mut self, row: usize) -> &mut [VgaChar] {
+        let index = self.lines.len() - self.rows + row;
+        self.lines.row_mut(index)
+    }
+}
+
+impl fmt::Write for Terminal<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for char in s.chars() {
+            self.put_char(char);
+        }
+        self.draw();
+        Ok(())
+    }
+}
+
+/// Writes text into a row of chars from `col` on, dropping what does not fit.
+struct RowWriter<'c> {
+    chars: &'c mut [VgaChar],
+    col: usize,
+    style: VgaStyle,
+}
+
+impl fmt::Write for RowWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for char in s.chars() {
+            if let Some(cell) = self.chars.get_mut(self.col) {
+                *cell = VgaChar::new(char, self.style);
+            }
+            self.col += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vga::char::{VgaChar, VgaStyle};
+    use crate::vga::color::VgaColor;
+    use crate::vga::font::VgaFont;
+    use crate::vga::terminal::{CursorStyle, Terminal};
+    use crate::vga::tests::test_framebuffer;
+    use crate::vga::{VgaError, VgaScreen};
+    use alloc::boxed::Box;
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use core::fmt::Write;
+
+    const COLS: usize = 20;
+    const ROWS: usize = 6;
+
+    /// The size of a screen of `COLS` by `ROWS` chars of the default font.
+    fn screen_size() -> (usize, usize) {
+        let font = VgaFont::default();
+        (COLS * font.char_width(), ROWS * font.char_height())
+    }
+
+    /// A screen of `COLS` by `ROWS` chars drawing to `bytes`, which must outlive it.
+    fn screen(bytes: &mut Vec<u8>) -> VgaScreen<'static> {
+        let (width, height) = screen_size();
+        bytes.resize(width * height, 0);
+        let framebuffer = Box::leak(Box::new(test_framebuffer(bytes, width, height)));
+        VgaScreen::new(framebuffer).unwrap()
+    }
+
+    fn terminal(bytes: &mut Vec<u8>) -> Terminal<'static> {
+        Terminal::new(screen(bytes)).unwrap()
+    }
+
+    fn row_text(screen: &VgaScreen, row: usize) -> String {
+        let cols = screen.text_cols();
+        let chars = &screen.text_buffer()[row * cols..(row + 1) * cols];
+        String::from(
+            chars
+                .iter()
+                .map(|char| char.char)
+                .collect::<String>()
+                .trim_end(),
+        )
+    }
+
+    #[test]
+    fn terminal_test() {
+        let mut bytes = Vec::new();
+        let mut terminal = terminal(&mut bytes);
+
+        write!(terminal, "ab\tc\nxyz\x08\x08Q\rW").unwrap();
+        assert_eq!(row_text(terminal.screen(), 0), "ab      c");
+        assert_eq!(row_text(terminal.screen(), 1), "WQ");
+        assert_eq!(terminal.cursor(), (1, 1));
+
+        // Long lines wrap, and backspace goes back over the wrap
+        terminal.clear();
+        let line: String = (0..COLS + 2).map(|_| '-').collect();
+        write!(terminal, "{line}").unwrap();
+        assert_eq!(row_text(terminal.screen(), 1), "--");
+        write!(terminal, "\x08\x08\x08").unwrap();
+        assert_eq!(terminal.cursor(), (COLS - 1, 0));
+        assert_eq!(row_text(terminal.screen(), 1), "");
+
+        // Reaching the bottom scrolls everything up
+        terminal.clear();
+        for i in 0..ROWS + 3 {
+            write!(terminal, "\n{i}").unwrap();
+        }
+        assert_eq!(terminal.cursor().1, ROWS - 1);
+        assert_eq!(row_text(terminal.screen(), 0), "3");
+        let last = ROWS + 2;
+        assert_eq!(row_text(terminal.screen(), ROWS - 1), format!("{last}"));
+
+        // Scrolling moves the drawn pixels, which must match drawing everything again
+        let text: Vec<VgaChar> = terminal.screen().text_buffer().iter().copied().collect();
+        let cursor = terminal.screen().cursor();
+        drop(terminal);
+        let mut expected = Vec::new();
+        let mut screen = screen(&mut expected);
+        screen.text_buffer_mut().copy_from_slice(&text);
+        screen.set_cursor(cursor);
+        screen.draw();
+        drop(screen);
+        assert!(bytes == expected);
+    }
+
+    #[test]
+    fn escape_sequence_test() {
+        let mut bytes = Vec::new();
+        let mut terminal = terminal(&mut bytes);
+
+        write!(terminal, "\x1b[31;42mred\x1b[0m plain\x1b[3;5Hx\x1b[s").unwrap();
+        let red = terminal.screen().text_buffer()[0];
+        assert_eq!(red.char, 'r');
+        assert_eq!(red.style.foreground, VgaColor::dark_red());
+        assert_eq!(red.style.background, VgaColor::dark_green());
+        assert_eq!(
+            terminal.screen().text_buffer()[4].style,
+            VgaStyle::default()
+        );
+        assert_eq!(terminal.cursor(), (5, 2));
+
+        write!(terminal, "\x1b[H\x1b[2C\x1b[K\x1b[u\x1b[2Dy\x1b[?25l").unwrap();
+        assert_eq!(row_text(terminal.screen(), 0), "re");
+        assert_eq!(row_text(terminal.screen(), 2), "   yx");
+        assert_eq!(terminal.cursor_style(), CursorStyle::Hidden);
+        assert_eq!(terminal.screen().cursor(), None);
+
+        write!(terminal, "\x1b[2J").unwrap();
+        assert_eq!(row_text(terminal.screen(), 2), "");
+        assert_eq!(terminal.cursor(), (4, 2));
+    }
+
+    #[test]
+    fn reserve_rows_test() {
+        let mut bytes = Vec::new();
+        let mut terminal = terminal(&mut bytes);
+        terminal.reserve_rows(ROWS).unwrap();
+        assert_eq!(terminal.rows(), 1);
+        terminal.reserve_rows(1).unwrap();
+        assert_eq!(terminal.rows(), ROWS - 1);
+        let footer_row = terminal.rows();
+        terminal
+            .screen_mut()
+            .print_text(0, footer_row, "footer", VgaStyle::default());
+
+        for i in 0..ROWS + 3 {
+            write!(terminal, "\n{i}").unwrap();
+        }
+        write!(terminal, "\x1b[999;1Hx\x1b[2J").unwrap();
+        assert_eq!(terminal.cursor(), (1, ROWS - 2));
+        assert_eq!(row_text(terminal.screen(), ROWS - 1), "footer");
+        terminal.clear();
+        assert_eq!(row_text(terminal.screen(), ROWS - 1), "footer");
+
+        // Only the rows of the terminal are moved when it scrolls
+        write!(terminal, "{}", "\n".repeat(ROWS)).unwrap();
+        let text: Vec<VgaChar> = terminal.screen().text_buffer().iter().copied().collect();
+        let cursor = terminal.screen().cursor();
+        drop(terminal);
+        let mut expected = Vec::new();
+        let mut screen = screen(&mut expected);
+        screen.text_buffer_mut().copy_from_slice(&text);
+        screen.set_cursor(cursor);
+        screen.draw();
+        drop(screen);
+        assert!(bytes == expected);
+    }
+
+    #[test]
+    fn scrollback_test() {
+        let mut bytes = Vec::new();
+        let mut terminal = Terminal::with_scrollback(screen(&mut bytes), 5).unwrap();
+
+        for i in 0..ROWS + 3 {
+            writeln!(terminal, "{i}").unwrap();
+        }
+        assert_eq!(terminal.scrollback_len(), 4);
+        assert_eq!(row_text(terminal.screen(), 0), "4");
+
+        terminal.scroll_up(3);
+        assert_eq!(terminal.view_offset(), 3);
+        assert!(row_text(terminal.screen(), 0).starts_with('1'));
+        assert!(row_text(terminal.screen(), 0).ends_with("Scrollback 3/4"));
+        assert_eq!(terminal.screen().cursor(), None);
+
+        // New output keeps the view on the same rows, until they are dropped
+        write!(terminal, "a\nb\nc\n").unwrap();
+        assert_eq!(terminal.view_offset(), 5);
+        assert!(row_text(terminal.screen(), 0).starts_with('2'));
+        terminal.page_up();
+        assert_eq!(terminal.view_offset(), 5);
+
+        terminal.scroll_to_bottom();
+        assert_eq!(terminal.view_offset(), 0);
+        assert_eq!(row_text(terminal.screen(), 0), "7");
+        assert_eq!(row_text(terminal.screen(), ROWS - 2), "c");
+        assert!(terminal.screen().cursor().is_some());
+    }
+
+    #[test]
+    fn font_test() {
+        let mut bytes = Vec::new();
+        let mut terminal = terminal(&mut bytes);
+        let (width, _) = screen_size();
+        write!(terminal, "{}", "x".repeat(COLS + 1)).unwrap();
+
+        assert_eq!(
+            VgaFont::noto(14).unwrap_err(),
+            VgaError::UnsupportedFontSize(14)
+        );
+        let big = VgaFont::noto(32).unwrap();
+        let cols = width / big.char_width();
+        terminal.set_font(big).unwrap();
+        assert_eq!(terminal.screen().text_cols(), cols);
+        assert_eq!(terminal.screen().text_rows(), ROWS / 2);
+        assert_eq!(terminal.cursor(), (0, 0));
+        assert_eq!(row_text(terminal.screen(), 0), "");
+
+        write!(terminal, "{}", "y".repeat(cols + 1)).unwrap();
+        assert_eq!(row_text(terminal.screen(), 1), "y");
+        // The text is drawn with the new font
+        assert!(bytes[..width * 32].iter().any(|&pixel| pixel != 0));
+    }
+}